@@ -0,0 +1,41 @@
+//! 16 senders sending to one receiver as fast as they can.
+//!
+//! Compare the mutex queue with the one that skips the lock for plain sends:
+//!
+//! cargo run --release --example contention
+//! cargo run --release --example contention --features crossbeam
+use futures::{executor::block_on, stream::StreamExt};
+use kiss_queue::mpsc;
+use std::{thread, time::Instant};
+
+const SENDERS: u64 = 16;
+const VALUES: u64 = 100_000;
+
+fn main() {
+    let (sender, receiver) = mpsc::<u64>();
+    let start = Instant::now();
+    let threads = (0..SENDERS)
+        .map(|_| {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                for i in 0..VALUES {
+                    sender.send(i).unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(sender);
+    let sum = block_on(receiver.fold(0, |sum, value| async move { sum + value }));
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(sum, SENDERS * VALUES * (VALUES - 1) / 2);
+    println!(
+        "{} senders, {} values: {:?}, {:.0} values/s",
+        SENDERS,
+        SENDERS * VALUES,
+        elapsed,
+        (SENDERS * VALUES) as f64 / elapsed.as_secs_f64()
+    );
+}
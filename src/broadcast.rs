@@ -0,0 +1,257 @@
+//! a channel where every receiver gets every value
+use crate::{
+    sync::{lock, Mutex},
+    Producer, SendError, WaitQueue,
+};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{fmt, pin::Pin, task::Context};
+use futures::{
+    stream::{FusedStream, Stream},
+    task::Poll,
+};
+#[cfg(feature = "std")]
+use std::error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecvError {
+    /// the receiver fell behind by this many values, which are lost to it
+    Lagged(u64),
+}
+
+#[cfg(feature = "std")]
+impl error::Error for RecvError {}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Lagged(n) => write!(f, "Lagged({})", n),
+        }
+    }
+}
+
+struct BroadcastInner<T> {
+    /// the last `capacity` values that were sent
+    buffer: VecDeque<T>,
+    /// sequence number of the first value in the buffer
+    head: u64,
+    capacity: usize,
+    receiver_wakers: WaitQueue,
+    sender_count: usize,
+    receiver_count: usize,
+}
+
+impl<T> BroadcastInner<T> {
+    /// sequence number of the next value to be sent
+    fn tail(&self) -> u64 {
+        self.head + self.buffer.len() as u64
+    }
+}
+
+pub struct Sender<T>(Arc<Mutex<BroadcastInner<T>>>);
+
+impl<T> Sender<T> {
+    // true if all receivers are dropped, and therefore there is no point in sending anymore
+    pub fn is_cancelled(&self) -> bool {
+        lock(&self.0).receiver_count == 0
+    }
+
+    /// send a value to all receivers, returning the number of receivers.
+    ///
+    /// If the buffer is full, the oldest value is dropped, and receivers that have not
+    /// seen it yet will get a `RecvError::Lagged`.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut inner = lock(&self.0);
+        if inner.receiver_count == 0 {
+            return Err(SendError::ReceiverDropped(value));
+        }
+        inner.buffer.push_back(value);
+        if inner.buffer.len() > inner.capacity {
+            inner.buffer.pop_front();
+            inner.head += 1;
+        }
+        inner.receiver_wakers.wake_all();
+        Ok(inner.receiver_count)
+    }
+}
+
+/// the buffer never fills up, the oldest value is dropped instead
+impl<T> Producer<T> for Sender<T> {
+    fn try_send(&self, value: T) -> Result<(), SendError<T>> {
+        self.send(value).map(|_| ())
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(lock(&self.0).capacity)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        Sender::is_cancelled(self)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        lock(&self.0).sender_count += 1;
+        Sender(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.0);
+        inner.sender_count -= 1;
+        if inner.sender_count == 0 {
+            inner.receiver_wakers.wake_all();
+        }
+    }
+}
+
+/// a receiver that independently observes every value sent after its creation
+pub struct Receiver<T> {
+    inner: Arc<Mutex<BroadcastInner<T>>>,
+    /// sequence number of the next value to yield
+    next: u64,
+    key: Option<usize>,
+}
+
+/// a clone starts at the current tail, so it only sees values sent after the clone
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        let mut inner = lock(&self.inner);
+        inner.receiver_count += 1;
+        Receiver {
+            inner: self.inner.clone(),
+            next: inner.tail(),
+            key: None,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.inner);
+        inner.receiver_count -= 1;
+        inner.receiver_wakers.remove(self.key.take());
+    }
+}
+
+impl<T: Clone> Stream for Receiver<T> {
+    type Item = Result<T, RecvError>;
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let mut inner = lock(&this.inner);
+        if this.next < inner.head {
+            // skip ahead to the oldest value we still have
+            let lagged = inner.head - this.next;
+            this.next = inner.head;
+            Poll::Ready(Some(Err(RecvError::Lagged(lagged))))
+        } else if this.next < inner.tail() {
+            let value = inner.buffer[(this.next - inner.head) as usize].clone();
+            this.next += 1;
+            Poll::Ready(Some(Ok(value)))
+        } else if inner.sender_count == 0 {
+            Poll::Ready(None)
+        } else {
+            inner.receiver_wakers.register(&mut this.key, ctx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T: Clone> FusedStream for Receiver<T> {
+    fn is_terminated(&self) -> bool {
+        let inner = lock(&self.inner);
+        self.next == inner.tail() && inner.sender_count == 0
+    }
+}
+
+/// a sender for `broadcast_shared`, which wraps every value in an `Arc`
+pub struct SharedSender<T>(Sender<Arc<T>>);
+
+impl<T> Clone for SharedSender<T> {
+    fn clone(&self) -> Self {
+        SharedSender(self.0.clone())
+    }
+}
+
+impl<T> SharedSender<T> {
+    // true if all receivers are dropped, and therefore there is no point in sending anymore
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+
+    /// send a value to all receivers, returning the number of receivers
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        self.0.send(Arc::new(value)).map_err(|e| {
+            // nobody else has seen the arc
+            e.map(|value| Arc::try_unwrap(value).ok().expect("value was not sent"))
+        })
+    }
+}
+
+/// like `broadcast`, but all receivers share each value using an `Arc`, so large values
+/// don't have to be cloned for every receiver. `T` does not even have to be `Clone`.
+pub fn broadcast_shared<T>(capacity: usize) -> (SharedSender<T>, Receiver<Arc<T>>) {
+    let (sender, receiver) = broadcast(capacity);
+    (SharedSender(sender), receiver)
+}
+
+/// a channel where every receiver gets every value, keeping the last `capacity` values
+/// for receivers that are behind.
+///
+/// Panics if `capacity` is 0.
+pub fn broadcast<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast capacity must be at least 1");
+    let inner = Arc::new(Mutex::new(BroadcastInner {
+        buffer: VecDeque::with_capacity(capacity),
+        head: 0,
+        capacity,
+        receiver_wakers: WaitQueue::default(),
+        sender_count: 1,
+        receiver_count: 1,
+    }));
+    let receiver = Receiver {
+        inner: inner.clone(),
+        next: 0,
+        key: None,
+    };
+    (Sender(inner), receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use futures::{executor::block_on, stream::StreamExt};
+
+    #[test]
+    fn every_receiver_gets_every_value() {
+        let (sender, receiver1) = broadcast::<u32>(2);
+        sender.send(0).unwrap();
+        let receiver2 = receiver1.clone();
+        assert_eq!(sender.send(1).unwrap(), 2);
+        assert_eq!(sender.send(2).unwrap(), 2);
+        drop(sender);
+        assert_eq!(
+            block_on(receiver1.collect::<Vec<_>>()),
+            vec![Err(RecvError::Lagged(1)), Ok(1), Ok(2)]
+        );
+        assert_eq!(block_on(receiver2.collect::<Vec<_>>()), vec![Ok(1), Ok(2)]);
+    }
+
+    #[test]
+    fn shared_values() {
+        // not Clone
+        #[derive(Debug, PartialEq)]
+        struct Big(u32);
+        let (sender, mut receiver1) = broadcast_shared::<Big>(2);
+        let sender = sender.clone();
+        let mut receiver2 = receiver1.clone();
+        sender.send(Big(1)).unwrap();
+        let value1 = block_on(receiver1.next()).unwrap().unwrap();
+        let value2 = block_on(receiver2.next()).unwrap().unwrap();
+        assert!(Arc::ptr_eq(&value1, &value2));
+        drop((receiver1, receiver2));
+        assert_eq!(sender.send(Big(2)), Err(SendError::ReceiverDropped(Big(2))));
+    }
+}
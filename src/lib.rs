@@ -1,181 +1,5240 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+// all the synchronization is done by the mutex. A faster path that needs unsafe code
+// would have to go behind a non-default feature, so the default build can keep this.
+#![forbid(unsafe_code)]
+extern crate alloc;
+// the tests need std for the test harness and threads, even for a no_std build
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use]
+extern crate std;
+
+pub mod broadcast;
+pub mod mpmc;
+pub mod oneshot;
+pub mod priority;
+mod sync;
+pub mod watch;
+
+pub use broadcast::{broadcast, broadcast_shared};
+pub use mpmc::mpmc;
+pub use oneshot::oneshot;
+pub use priority::priority_queue;
+pub use watch::watch;
+
+use crate::sync::{lock, Mutex, MutexGuard};
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+#[cfg(all(feature = "crossbeam", not(feature = "std")))]
+use core::hint::spin_loop;
+#[cfg(feature = "crossbeam")]
+use core::sync::atomic::fence;
+use core::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Waker},
+};
+#[cfg(feature = "crossbeam")]
+use crossbeam_queue::SegQueue;
+#[cfg(feature = "std")]
+use futures::task::{self, ArcWake};
 use futures::{
-    stream::{FusedStream, Stream},
+    stream::{FusedStream, Stream, StreamExt},
     task::Poll,
 };
+#[cfg(feature = "std")]
 use std::{
-    collections::VecDeque,
-    error, fmt,
-    pin::Pin,
-    sync::{Arc, Mutex},
-    task::{Context, Waker},
+    error, thread,
+    time::{Duration, Instant},
 };
 
-pub struct QueueInner<T> {
-    queue: VecDeque<T>,
-    waker: Option<Waker>,
-    receiver_dropped: bool,
+/// tasks that are parked waiting for something to happen.
+///
+/// Every waiter gets a key on first registration, so it can remove its waker when it
+/// stops waiting, e.g. because its future got dropped.
+#[derive(Default)]
+struct WaitQueue {
+    waiters: VecDeque<(usize, Waker)>,
+    next_key: usize,
 }
 
-#[derive(Clone)]
-pub struct Sender<T>(Arc<Mutex<QueueInner<T>>>);
+impl WaitQueue {
+    fn register(&mut self, key: &mut Option<usize>, waker: &Waker) {
+        if let Some(key) = *key {
+            if let Some((_, current)) = self.waiters.iter_mut().find(|(k, _)| *k == key) {
+                // avoid the clone when the same task polls repeatedly
+                if !current.will_wake(waker) {
+                    *current = waker.clone();
+                }
+                return;
+            }
+        }
+        match *key {
+            // we were woken, but did not get what we were waiting for. Keep our place
+            Some(key) => self.waiters.push_front((key, waker.clone())),
+            None => {
+                self.next_key += 1;
+                *key = Some(self.next_key);
+                self.waiters.push_back((self.next_key, waker.clone()));
+            }
+        }
+    }
 
-#[derive(Debug, Clone)]
-pub enum SendError {
-    ReceiverDropped,
-}
+    /// remove a waiter, returning false if it was not waiting anymore because it got woken
+    fn remove(&mut self, key: Option<usize>) -> bool {
+        match key {
+            Some(key) => {
+                let len = self.waiters.len();
+                self.waiters.retain(|(k, _)| *k != key);
+                self.waiters.len() != len
+            }
+            None => false,
+        }
+    }
 
-impl error::Error for SendError {}
+    /// wake the `n` waiters that have been waiting the longest
+    fn wake(&mut self, n: usize) {
+        let n = n.min(self.waiters.len());
+        for (_, waker) in self.waiters.drain(..n) {
+            waker.wake();
+        }
+    }
 
-impl fmt::Display for SendError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            SendError::ReceiverDropped => write!(f, "ReceiverDropped")
+    fn wake_all(&mut self) {
+        let waiters = mem::take(&mut self.waiters);
+        for (i, (_, waker)) in waiters.iter().enumerate() {
+            // several waiters in the same task share a waker, which only needs one wakeup
+            if !waiters
+                .iter()
+                .take(i)
+                .any(|(_, other)| other.will_wake(waker))
+            {
+                waker.wake_by_ref();
+            }
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum SinkError {
+/// what a bounded queue does when a value is sent while it is at capacity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// `Sender::send` fails with `SendError::Full`, async senders wait for room
+    Block,
+    /// drop the oldest queued value to make room for the new one. Values sent with
+    /// `Sender::send_priority` are only dropped for other high priority values
+    DropOldest,
+    /// drop the value that is being sent
+    DropNewest,
+    /// all senders fail with a `Full` error, async senders don't wait
+    Error,
+}
+
+/// the result of `Sender::send_with_outcome`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendOutcome<T> {
+    /// the value was queued. Contains the queue len after the send
+    Sent(usize),
+    /// the value was queued, and the oldest value was dropped to make room for it
+    DroppedOldest(T),
+    /// the queue was full, so the value that was sent got dropped
+    DroppedNewest(T),
+    /// the value was equal to the last queued value, so it was not queued.
+    /// Only returned by `Sender::send_dedup`
+    Duplicate(T),
+}
+
+/// what happened to a queue, reported to the observer of `mpsc_observed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelEvent {
+    /// a value was queued. Contains the queue len after the send
+    Enqueued { len: usize },
+    /// values were received, see `QueueMetrics` for what counts. Contains the queue len
+    /// afterwards
+    Dequeued { len: usize },
+    /// a sender was dropped. Contains the number of senders that are left
+    SenderDropped { remaining: usize },
+    /// the receiver was dropped
     ReceiverDropped,
+}
+
+type Observer = Arc<dyn Fn(ChannelEvent) + Send + Sync>;
+
+/// the result of `Receiver::recv_or_closed`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecvResult<T> {
+    /// the next value
+    Value(T),
+    /// the queue is drained, and no more values can arrive
     Closed,
 }
 
-impl error::Error for SinkError {}
+/// why no more values can arrive, see `Receiver::close_reason`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// the last sender was dropped
+    AllSendersDropped,
+    /// the queue was closed with `Sender::close` or `Receiver::close`
+    Closed,
+    /// a sender called `Sender::abort`
+    Aborted,
+}
 
-impl fmt::Display for SinkError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            SinkError::ReceiverDropped => write!(f, "ReceiverDropped"),
-            SinkError::Closed => write!(f, "Closed"),
-        }
+/// how full a queue is compared to the thresholds from `mpsc_with_thresholds`, see
+/// `Sender::send_with_backpressure_hint`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BackpressureLevel {
+    /// below the elevated threshold, or the queue has no thresholds
+    Ok,
+    /// at or above the elevated threshold, time to slow down
+    Elevated,
+    /// at or above the critical threshold, time to shed load
+    Critical,
+}
+
+/// the contents of a queue, e.g. to dump the backlog of a stuck pipeline and replay
+/// it later using `mpsc_from_snapshot`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct QueueSnapshot<T>(pub Vec<T>);
+
+impl<T> From<Vec<T>> for QueueSnapshot<T> {
+    fn from(values: Vec<T>) -> Self {
+        QueueSnapshot(values)
     }
 }
 
-impl<T> Sender<T> {
-    /// current queue len. This can be used to detect when the receiver is lagging
-    pub fn queue_len(&self) -> usize {
-        self.0.lock().unwrap().queue.len()
+/// cumulative counters of a queue, see `Sender::metrics`.
+///
+/// Values count as received when the receiver takes them from the front of the queue,
+/// including the ones that `retain_recv`, `wait_for` or `discard_while` skip. Values
+/// that are taken out of order by `drain_filter` or `split_off`, or that are dropped
+/// by `DropOldest` or with the receiver, don't count, and don't fire
+/// `ChannelEvent::Dequeued` either.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueMetrics {
+    /// number of values that were queued. Values dropped by the overflow policy don't count
+    pub total_sent: u64,
+    /// number of values the receiver took out of the queue
+    pub total_received: u64,
+    /// the largest queue len seen so far, useful to pick a capacity for a bounded queue
+    pub high_water_mark: usize,
+}
+
+pub struct QueueInner<T> {
+    queue: VecDeque<T>,
+    waker: Option<Waker>,
+    receiver_dropped: bool,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    sender_wakers: WaitQueue,
+    /// sinks waiting for the queue to become empty
+    drain_wakers: WaitQueue,
+    /// `Closed` futures waiting for the receiver to go away
+    closed_wakers: WaitQueue,
+    /// set by `Sender::close`. No more values are accepted, but the queue still drains
+    closed: bool,
+    /// set by `Receiver::pause`. The receiver yields nothing, and is not woken
+    paused: bool,
+    /// the first reason why no more values can arrive
+    close_reason: Option<CloseReason>,
+    /// waker of `Receiver::all_senders_dropped`, separate from the stream waker
+    senders_dropped_waker: Option<Waker>,
+    /// called for every `ChannelEvent`, while the queue is locked
+    observer: Option<Observer>,
+    /// number of live `Sender`s. Tracked explicitly since not every reference to the
+    /// queue is a sender
+    sender_count: usize,
+    /// slots claimed by `Permit`s and sinks, which count towards the capacity
+    reserved: usize,
+    /// the part of `reserved` that is held by `Permit`s and `OwnedPermit`s. Those can
+    /// still send after the queue is closed, so the receiver has to wait for them
+    permits: usize,
+    /// number of values at the front of the queue that were sent with high priority
+    high_len: usize,
+    /// elevated and critical queue len for `BackpressureLevel`
+    thresholds: Option<(usize, usize)>,
+    /// sequence number for the next value that is sent, see `Sender::send_seq`
+    next_seq: u64,
+    /// limit on the total weight of the queued values, see `weighted_bounded`
+    weight: Option<Weight<T>>,
+    /// `None` for a queue that was built with `ChannelBuilder::metrics(false)`
+    #[cfg(feature = "metrics")]
+    metrics: Option<QueueMetrics>,
+}
+
+/// total weight of the queued values of a `weighted_bounded` queue
+struct Weight<T> {
+    max: usize,
+    current: usize,
+    weigh: Box<dyn Fn(&T) -> usize + Send>,
+}
+
+impl<T> QueueInner<T> {
+    fn new(capacity: Option<usize>, policy: OverflowPolicy) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            waker: None,
+            receiver_dropped: false,
+            capacity,
+            policy,
+            sender_wakers: WaitQueue::default(),
+            drain_wakers: WaitQueue::default(),
+            closed_wakers: WaitQueue::default(),
+            sender_count: 1,
+            reserved: 0,
+            permits: 0,
+            high_len: 0,
+            thresholds: None,
+            next_seq: 0,
+            weight: None,
+            closed: false,
+            paused: false,
+            close_reason: None,
+            senders_dropped_waker: None,
+            observer: None,
+            #[cfg(feature = "metrics")]
+            metrics: Some(QueueMetrics::default()),
+        }
     }
 
-    // true if the receiver is dropped, and therefore there is no point in sending anymore
-    pub fn is_cancelled(&self) -> bool {
-        self.0.lock().unwrap().receiver_dropped
+    /// true if a sender is allowed to push another item
+    fn has_room(&self) -> bool {
+        if let Some(weight) = &self.weight {
+            if weight.current >= weight.max {
+                return false;
+            }
+        }
+        match self.capacity {
+            None => true,
+            // a rendezvous channel only takes an item if the receiver is waiting for it
+            Some(0) => {
+                self.queue.is_empty() && self.reserved == 0 && self.waker.is_some() && !self.paused
+            }
+            Some(capacity) => self.queue.len() + self.reserved < capacity,
+        }
     }
 
-    pub fn send(&self, value: T) -> std::result::Result<usize, SendError> {
-        let mut inner = self.0.lock().unwrap();
-        if !inner.receiver_dropped {
-            inner.queue.push_back(value);
-            let len = inner.queue.len();
-            // we only need to wake once
-            for waker in inner.waker.take() {
-                waker.wake_by_ref();
+    /// true if no more values can arrive.
+    ///
+    /// This uses the explicit sender count rather than the strong count of the arc, which
+    /// also counts temporary references, e.g. from `WeakSender::upgrade`. A closed queue
+    /// is only done once all permits are used or dropped.
+    fn senders_done(&self) -> bool {
+        (self.closed && self.permits == 0) || self.sender_count == 0
+    }
+
+    /// number of values that can be pushed right now
+    fn free_slots(&self) -> usize {
+        match self.capacity {
+            // we can't know how many values fit into the remaining weight
+            None => self.has_room() as usize * usize::MAX,
+            Some(0) => self.has_room() as usize,
+            Some(capacity) => capacity.saturating_sub(self.queue.len() + self.reserved),
+        }
+    }
+
+    /// true if an async sender has to wait for room
+    fn must_wait(&self) -> bool {
+        self.policy == OverflowPolicy::Block && !self.has_room()
+    }
+
+    /// like `must_wait`, but also waits if `value` would push a weighted queue over its
+    /// max weight
+    fn must_wait_for(&self, value: &T) -> bool {
+        self.must_wait() || (self.policy == OverflowPolicy::Block && !self.fits(self.weigh(value)))
+    }
+
+    /// weight of a value, 0 if the queue is not weighted
+    fn weigh(&self, value: &T) -> usize {
+        self.weight
+            .as_ref()
+            .map_or(0, |weight| (weight.weigh)(value))
+    }
+
+    /// true if values with a total weight of `extra` can be added without going over the
+    /// max weight. Anything fits into an empty queue, otherwise it could never be sent.
+    fn fits(&self, extra: usize) -> bool {
+        match &self.weight {
+            None => true,
+            Some(weight) => {
+                self.queue.is_empty() || weight.current.saturating_add(extra) <= weight.max
             }
-            Ok(len)
+        }
+    }
+
+    fn send(&mut self, value: T) -> Result<SendOutcome<T>, SendError<T>> {
+        if self.receiver_dropped {
+            Err(SendError::ReceiverDropped(value))
+        } else if self.closed {
+            Err(SendError::Closed(value))
         } else {
-            Err(SendError::ReceiverDropped)
+            self.offer(value).map_err(SendError::Full)
         }
     }
 
-    pub fn sink(self) -> Sink<T> {
-        Sink(Some(self))
+    /// push a value if there is room, otherwise apply the overflow policy.
+    ///
+    /// Hands back the value if the policy does not allow dropping anything.
+    fn offer(&mut self, value: T) -> Result<SendOutcome<T>, T> {
+        self.offer_at(value, false)
+    }
+
+    /// like `offer`, but a high priority value goes in after the other high priority
+    /// values, and before everything else
+    fn offer_at(&mut self, value: T, high: bool) -> Result<SendOutcome<T>, T> {
+        if self.has_room() && self.fits(self.weigh(&value)) {
+            return Ok(SendOutcome::Sent(self.push_at(value, high)));
+        }
+        match self.policy {
+            OverflowPolicy::Block | OverflowPolicy::Error => Err(value),
+            OverflowPolicy::DropNewest => {
+                // the value still gets a sequence number, so the drop shows up as a gap
+                self.next_seq += 1;
+                Ok(SendOutcome::DroppedNewest(value))
+            }
+            OverflowPolicy::DropOldest => match self.evict_oldest(high) {
+                Some(oldest) => {
+                    self.push_at(value, high);
+                    Ok(SendOutcome::DroppedOldest(oldest))
+                }
+                // a rendezvous channel has nothing to drop but the new value
+                None => {
+                    self.next_seq += 1;
+                    Ok(SendOutcome::DroppedNewest(value))
+                }
+            },
+        }
+    }
+
+    /// push a value and wake the receiver, returning the new queue len
+    fn push(&mut self, value: T) -> usize {
+        self.push_at(value, false)
+    }
+
+    fn push_at(&mut self, value: T, high: bool) -> usize {
+        if let Some(weight) = &mut self.weight {
+            weight.current += (weight.weigh)(&value);
+        }
+        if high {
+            self.queue.insert(self.high_len, value);
+            self.high_len += 1;
+        } else {
+            self.queue.push_back(value);
+        }
+        self.next_seq += 1;
+        self.observe(ChannelEvent::Enqueued {
+            len: self.queue.len(),
+        });
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &mut self.metrics {
+            metrics.total_sent += 1;
+            metrics.high_water_mark = metrics.high_water_mark.max(self.queue.len());
+        }
+        self.wake_receiver();
+        self.queue.len()
+    }
+
+    fn wake_receiver(&mut self) {
+        // a paused receiver would just park again, it gets woken on resume
+        if self.paused {
+            return;
+        }
+        // we only need to wake once
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// take the first value, without waking anyone
+    fn pop_front(&mut self) -> Option<T> {
+        let value = self.queue.pop_front();
+        if let Some(value) = &value {
+            self.high_len = self.high_len.saturating_sub(1);
+            self.unweigh(value);
+        }
+        value
+    }
+
+    /// take the oldest value to make room for a new one, without waking anyone.
+    ///
+    /// Normal values go first, so a high priority value is only dropped to make room for
+    /// another high priority value.
+    fn evict_oldest(&mut self, high: bool) -> Option<T> {
+        if self.queue.len() > self.high_len {
+            let value = self.queue.remove(self.high_len);
+            if let Some(value) = &value {
+                self.unweigh(value);
+            }
+            value
+        } else if high {
+            self.pop_front()
+        } else {
+            None
+        }
+    }
+
+    /// pop a value, waking parked senders since there is now room
+    fn pop(&mut self) -> Option<T> {
+        let value = self.pop_front();
+        if value.is_some() {
+            self.popped(1);
+        }
+        value
+    }
+
+    /// move up to `max` values into `buf`, returning how many were moved
+    fn pop_many(&mut self, buf: &mut impl Extend<T>, max: usize) -> usize {
+        let n = max.min(self.queue.len());
+        if n > 0 {
+            if let Some(weight) = &mut self.weight {
+                let removed: usize = self.queue.iter().take(n).map(|v| (weight.weigh)(v)).sum();
+                weight.current -= removed;
+            }
+            buf.extend(self.queue.drain(..n));
+            self.high_len = self.high_len.saturating_sub(n);
+            self.popped(n);
+        }
+        n
+    }
+
+    /// take the weight of a value that is no longer queued off the total
+    fn unweigh(&mut self, value: &T) {
+        if let Some(weight) = &mut self.weight {
+            weight.current -= (weight.weigh)(value);
+        }
+    }
+
+    /// account for `n` values taken out of the queue, and wake everyone who is waiting
+    /// for the queue to shrink.
+    ///
+    /// Parked senders are woken oldest first, one for every value that was taken. On a
+    /// weighted queue a single value can make room for any number of values, so all of
+    /// them are woken.
+    fn popped(&mut self, n: usize) {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &mut self.metrics {
+            metrics.total_received += n as u64;
+        }
+        self.observe(ChannelEvent::Dequeued {
+            len: self.queue.len(),
+        });
+        self.made_room(n);
+    }
+
+    /// wake senders that wait for room after `n` values left the queue, and everyone who
+    /// waits for it to be drained
+    fn made_room(&mut self, n: usize) {
+        if self.weight.is_some() {
+            self.sender_wakers.wake_all();
+        } else {
+            self.sender_wakers.wake(n);
+        }
+        if self.queue.is_empty() {
+            self.drain_wakers.wake_all();
+        }
+    }
+
+    fn observe(&self, event: ChannelEvent) {
+        if let Some(observer) = &self.observer {
+            observer(event);
+        }
+    }
+
+    fn close(&mut self, reason: CloseReason) {
+        self.closed = true;
+        self.close_reason.get_or_insert(reason);
+        self.wake_receiver();
+        // parked senders won't get any room anymore
+        self.wake_senders();
+        self.closed_wakers.wake_all();
+    }
+
+    /// register a paused receiver to be woken on resume, returning true if paused.
+    ///
+    /// Unlike `park_receiver`, this does not let senders on a rendezvous channel proceed.
+    fn park_if_paused(&mut self, waker: &Waker) -> bool {
+        if self.paused {
+            self.store_waker(waker);
+        }
+        self.paused
+    }
+
+    /// register the receiver as waiting for values
+    fn park_receiver(&mut self, waker: &Waker) {
+        self.store_waker(waker);
+        // senders on a rendezvous channel can only proceed once we are waiting
+        if self.capacity == Some(0) {
+            self.sender_wakers.wake(1);
+        }
+    }
+
+    /// store the receiver waker. A receiver that is polled again by the same task does not
+    /// have to clone it every time
+    fn store_waker(&mut self, waker: &Waker) {
+        match &self.waker {
+            Some(stored) if stored.will_wake(waker) => {}
+            _ => self.waker = Some(waker.clone()),
+        }
+    }
+
+    /// claim a slot for a permit
+    fn claim_permit(&mut self) {
+        self.reserved += 1;
+        self.permits += 1;
+    }
+
+    /// give back the slot of a permit that is used or dropped
+    fn release_permit(&mut self) {
+        self.reserved -= 1;
+        self.permits -= 1;
+        // the receiver of a closed queue waits for the last permit to end the stream
+        if self.closed && self.permits == 0 {
+            self.wake_receiver();
+        }
+    }
+
+    fn wake_senders(&mut self) {
+        self.sender_wakers.wake_all();
+    }
+
+    /// true if a plain send does nothing but push the value and wake the receiver, so it
+    /// does not need the lock
+    #[cfg(feature = "crossbeam")]
+    fn lock_free(&self) -> bool {
+        self.capacity.is_none()
+            && self.weight.is_none()
+            && self.observer.is_none()
+            && !self.closed
+            && !self.receiver_dropped
+    }
+
+    /// remove a sender that stops waiting without sending.
+    ///
+    /// If it was woken already, the wakeup is passed on, so the room it was woken for
+    /// does not go unused.
+    fn unpark_sender(&mut self, key: Option<usize>) {
+        if !self.sender_wakers.remove(key) && !self.must_wait() {
+            self.sender_wakers.wake(1);
+        }
     }
 }
 
-impl<T> Drop for Sender<T> {
-    fn drop(&mut self) {
-        // we might be the last sender, and the receiver might be waiting for us.
-        // this will cause some false wakeups, but that's ok.
-        for waker in self.0.lock().unwrap().waker.take() {
-            waker.wake_by_ref();
+/// the queue state behind its lock, plus copies of some of it that can be read without
+/// taking the lock.
+///
+/// With the `crossbeam` feature, plain sends to an unbounded queue go to a lock free
+/// `SegQueue` instead, so senders don't wait for each other. Every lock moves those
+/// values into the queue first, so everything that needs the whole queue, like peeking,
+/// priority sends or snapshots, sees them in the order they were sent.
+struct Shared<T> {
+    inner: Mutex<QueueInner<T>>,
+    /// values sent without taking the lock, on their way into `inner.queue`
+    #[cfg(feature = "crossbeam")]
+    incoming: SegQueue<T>,
+    /// copy of `lock_free()`
+    #[cfg(feature = "crossbeam")]
+    lock_free: AtomicBool,
+    /// number of senders that are pushing to `incoming` right now
+    #[cfg(feature = "crossbeam")]
+    pushing: AtomicUsize,
+    /// true if the receiver is parked and has to be woken for a new value
+    #[cfg(feature = "crossbeam")]
+    receiver_waiting: AtomicBool,
+    /// copy of `queue.len()`
+    len: AtomicUsize,
+    /// copy of `receiver_dropped`
+    receiver_dropped: AtomicBool,
+    /// the largest `len` so far
+    high_water_mark: AtomicUsize,
+}
+
+impl<T> Shared<T> {
+    fn new(inner: QueueInner<T>) -> Self {
+        Self {
+            len: AtomicUsize::new(inner.queue.len()),
+            high_water_mark: AtomicUsize::new(inner.queue.len()),
+            receiver_dropped: AtomicBool::new(inner.receiver_dropped),
+            #[cfg(feature = "crossbeam")]
+            incoming: SegQueue::new(),
+            #[cfg(feature = "crossbeam")]
+            lock_free: AtomicBool::new(inner.lock_free()),
+            #[cfg(feature = "crossbeam")]
+            pushing: AtomicUsize::new(0),
+            #[cfg(feature = "crossbeam")]
+            receiver_waiting: AtomicBool::new(false),
+            inner: Mutex::new(inner),
+        }
+    }
+
+    fn lock(&self) -> Guard<'_, T> {
+        #[allow(unused_mut)]
+        let mut guard = Guard {
+            shared: self,
+            inner: lock(&self.inner),
+        };
+        #[cfg(feature = "crossbeam")]
+        guard.take_incoming();
+        guard
+    }
+
+    /// push a value without taking the lock, returning the queue len after the push.
+    ///
+    /// Hands back the value if the send has to take the lock, e.g. because the queue is
+    /// bounded or closed, or the receiver is parked.
+    #[cfg(feature = "crossbeam")]
+    fn try_push(&self, value: T) -> Result<usize, T> {
+        // a parked receiver has to be woken under the lock anyway
+        if self.receiver_waiting.load(Ordering::Relaxed) {
+            return Err(value);
+        }
+        self.pushing.fetch_add(1, Ordering::SeqCst);
+        if !self.lock_free.load(Ordering::SeqCst) {
+            self.pushing.fetch_sub(1, Ordering::SeqCst);
+            return Err(value);
         }
+        self.incoming.push(value);
+        self.pushing.fetch_sub(1, Ordering::SeqCst);
+        // pairs with the fence in `Guard::drop`: either the receiver sees the value before
+        // it parks, or we see that it is parked
+        fence(Ordering::SeqCst);
+        if self.receiver_waiting.load(Ordering::SeqCst) {
+            // taking the lock moves the value into the queue, which wakes the receiver
+            drop(self.lock());
+        }
+        Ok(self.len())
+    }
+
+    /// the queue len without taking the lock, including values that were sent without
+    /// the lock and are not in the queue yet
+    fn len(&self) -> usize {
+        let len = self.len.load(Ordering::Relaxed);
+        #[cfg(feature = "crossbeam")]
+        let len = len + self.incoming.len();
+        len
     }
 }
 
-impl<T> Drop for Receiver<T> {
-    fn drop(&mut self) {
-        let mut inner = self.0.lock().unwrap();
-        inner.receiver_dropped = true;
-        inner.waker = None;
+/// a lock on the queue state, which updates the copies when it is released
+struct Guard<'a, T> {
+    shared: &'a Shared<T>,
+    inner: MutexGuard<'a, QueueInner<T>>,
+}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = QueueInner<T>;
+
+    fn deref(&self) -> &QueueInner<T> {
+        &self.inner
     }
 }
 
-pub struct Receiver<T>(Arc<Mutex<QueueInner<T>>>);
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut QueueInner<T> {
+        &mut self.inner
+    }
+}
 
-impl<T> Stream for Receiver<T> {
-    type Item = T;
-    fn poll_next(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
-        let mut inner = self.0.lock().unwrap();
-        if let Some(value) = inner.queue.pop_front() {
-            Poll::Ready(Some(value))
-        } else if Arc::strong_count(&self.0) == 1 {
-            Poll::Ready(None)
-        } else {
-            inner.waker = Some(ctx.waker().clone());
-            Poll::Pending
+#[cfg(feature = "crossbeam")]
+impl<T> Guard<'_, T> {
+    /// move the values that were sent without the lock into the queue, in send order.
+    ///
+    /// Once the receiver is gone, they are left for `Receiver::drop`, which drops them
+    /// outside the lock.
+    fn take_incoming(&mut self) {
+        if self.inner.receiver_dropped {
+            return;
+        }
+        while let Some(value) = self.shared.incoming.pop() {
+            self.inner.push(value);
         }
     }
 }
 
-impl<T> FusedStream for Receiver<T> {
-    fn is_terminated(&self) -> bool {
-        Arc::strong_count(&self.0) == 1
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "crossbeam")]
+        {
+            // both copies are only written under the lock, so they are only stored, with
+            // the expensive ordering, if they change
+            let shared = self.shared;
+            let lock_free = self.inner.lock_free();
+            if shared.lock_free.load(Ordering::Relaxed) != lock_free {
+                shared.lock_free.store(lock_free, Ordering::SeqCst);
+                // a sender that saw the old value might still be pushing. Its value has
+                // to be in the queue before anyone can see e.g. that it is closed
+                while !lock_free && shared.pushing.load(Ordering::SeqCst) != 0 {
+                    // the sender might not be running right now
+                    #[cfg(feature = "std")]
+                    thread::yield_now();
+                    #[cfg(not(feature = "std"))]
+                    spin_loop();
+                }
+                self.take_incoming();
+            }
+            let waiting = self.inner.waker.is_some() && !self.inner.paused;
+            if shared.receiver_waiting.load(Ordering::Relaxed) != waiting {
+                shared.receiver_waiting.store(waiting, Ordering::SeqCst);
+            }
+            if waiting {
+                // pairs with the fence in `Shared::try_push`: a value that was pushed
+                // before the sender could see that we are waiting is taken here, which
+                // wakes the receiver
+                fence(Ordering::SeqCst);
+                if !shared.incoming.is_empty() {
+                    self.take_incoming();
+                    shared.receiver_waiting.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+        // this happens before the lock is released, so the copies are updated in order
+        let shared = self.shared;
+        let len = self.inner.queue.len();
+        shared.len.store(len, Ordering::Relaxed);
+        // only written under the lock, so there is no race between the load and the store
+        if len > shared.high_water_mark.load(Ordering::Relaxed) {
+            shared.high_water_mark.store(len, Ordering::Relaxed);
+        }
+        shared
+            .receiver_dropped
+            .store(self.inner.receiver_dropped, Ordering::Relaxed);
     }
 }
 
-pub struct Sink<T>(Option<Sender<T>>);
+/// the sending end of a queue. Like all handles of this crate, it is `Unpin` even if
+/// `T` is not, since values are never pinned
+pub struct Sender<T>(Arc<Shared<T>>);
 
-impl<T> futures::sink::Sink<T> for Sink<T> {
-    type Error = SinkError;
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.0.lock();
+        f.debug_struct("Sender")
+            .field("queue_len", &inner.queue.len())
+            .field("receiver_dropped", &inner.receiver_dropped)
+            .field("sender_count", &inner.sender_count)
+            .field("receiver_waiting", &inner.waker.is_some())
+            .finish()
+    }
+}
 
-    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(if let Some(inner) = &self.0 {
-            if !inner.is_cancelled() {
-                Ok(())
-            } else {
-                Err(SinkError::ReceiverDropped)
-            }
-        } else {
-            Err(SinkError::Closed)
-        })
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.lock().sender_count += 1;
+        Sender(self.0.clone())
     }
+}
 
-    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
-        if let Some(inner) = &self.0 {
-            match inner.send(item) {
-                Ok(_) => Ok(()),
-                Err(SendError::ReceiverDropped) => Err(SinkError::ReceiverDropped),
-            }
-        } else {
-            Err(SinkError::Closed)
+/// error for sending. Every variant contains the value that could not be sent, so it
+/// can be retried or sent somewhere else.
+///
+/// This is returned by the methods of `Sender`. The `Sink` uses `SinkError` instead, since
+/// the `Sink` trait takes the value before it knows whether it can be sent. Both
+/// convert into each other, so `?` works for code that uses both.
+#[derive(Clone, PartialEq, Eq)]
+pub enum SendError<T> {
+    /// the receiver is gone, so nobody would ever get the value
+    ReceiverDropped(T),
+    /// the queue was closed using `Sender::close`
+    Closed(T),
+    /// a bounded queue is at capacity
+    Full(T),
+}
+
+impl<T> SendError<T> {
+    /// convert the value, keeping the kind of error
+    pub(crate) fn map<U>(self, f: impl FnOnce(T) -> U) -> SendError<U> {
+        match self {
+            SendError::ReceiverDropped(value) => SendError::ReceiverDropped(f(value)),
+            SendError::Closed(value) => SendError::Closed(f(value)),
+            SendError::Full(value) => SendError::Full(f(value)),
+        }
+    }
+
+    /// the value that could not be sent
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::ReceiverDropped(value)
+            | SendError::Closed(value)
+            | SendError::Full(value) => value,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> error::Error for SendError<T> {}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::ReceiverDropped(_) => write!(f, "ReceiverDropped(..)"),
+            SendError::Closed(_) => write!(f, "Closed(..)"),
+            SendError::Full(_) => write!(f, "Full(..)"),
         }
     }
+}
 
-    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::ReceiverDropped(_) => write!(f, "ReceiverDropped"),
+            SendError::Closed(_) => write!(f, "Closed"),
+            SendError::Full(_) => write!(f, "Full"),
+        }
     }
+}
+
+/// error for `Sender::try_send`, which always hands back the value
+#[derive(Clone, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// a bounded queue is at capacity
+    Full(T),
+    /// the receiver is gone or the queue is closed
+    Disconnected(T),
+}
 
-    fn poll_close(
-        mut self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
-    ) -> Poll<Result<(), Self::Error>> {
-        self.0 = None;
-        Poll::Ready(Ok(()))
+impl<T> TrySendError<T> {
+    /// the value that could not be sent
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(value) | TrySendError::Disconnected(value) => value,
+        }
     }
 }
 
-pub fn mpsc<T>() -> (Sender<T>, Receiver<T>) {
-    let inner: Arc<Mutex<QueueInner<T>>> = Arc::new(Mutex::new(QueueInner {
-        queue: VecDeque::new(),
-        waker: None,
-        receiver_dropped: false,
-    }));
-    (Sender(inner.clone()), Receiver(inner))
+#[cfg(feature = "std")]
+impl<T> error::Error for TrySendError<T> {}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Full(..)"),
+            TrySendError::Disconnected(_) => write!(f, "Disconnected(..)"),
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Full"),
+            TrySendError::Disconnected(_) => write!(f, "Disconnected"),
+        }
+    }
+}
 
-    #[test]
-    fn smoke() {}
+/// error for `Sender::send_timeout`, which always hands back the value
+#[cfg(feature = "std")]
+#[derive(Clone, PartialEq, Eq)]
+pub enum SendTimeoutError<T> {
+    /// there was no room in the queue before the timeout
+    Timeout(T),
+    /// the receiver is gone or the queue is closed
+    Disconnected(T),
+}
+
+#[cfg(feature = "std")]
+impl<T> SendTimeoutError<T> {
+    /// the value that could not be sent
+    pub fn into_inner(self) -> T {
+        match self {
+            SendTimeoutError::Timeout(value) | SendTimeoutError::Disconnected(value) => value,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> error::Error for SendTimeoutError<T> {}
+
+#[cfg(feature = "std")]
+impl<T> fmt::Debug for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => write!(f, "Timeout(..)"),
+            SendTimeoutError::Disconnected(_) => write!(f, "Disconnected(..)"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> fmt::Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => write!(f, "Timeout"),
+            SendTimeoutError::Disconnected(_) => write!(f, "Disconnected"),
+        }
+    }
+}
+
+/// error of the sinks in this crate, the same kinds of errors as `SendError`, but
+/// without the value.
+#[derive(Debug, Clone)]
+pub enum SinkError {
+    /// the receiver is gone
+    ReceiverDropped,
+    /// the queue was closed using `Sender::close`, or the sink itself was closed
+    Closed,
+    /// the queue is at capacity and uses `OverflowPolicy::Error`
+    Full,
+}
+
+#[cfg(feature = "std")]
+impl error::Error for SinkError {}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkError::ReceiverDropped => write!(f, "ReceiverDropped"),
+            SinkError::Closed => write!(f, "Closed"),
+            SinkError::Full => write!(f, "Full"),
+        }
+    }
+}
+
+/// the same kind of error, without the value
+impl<T> From<SendError<T>> for SinkError {
+    fn from(error: SendError<T>) -> Self {
+        match error {
+            SendError::ReceiverDropped(_) => SinkError::ReceiverDropped,
+            SendError::Closed(_) => SinkError::Closed,
+            SendError::Full(_) => SinkError::Full,
+        }
+    }
+}
+
+/// the same kind of error. A sink has handed the value over already, so there is none
+impl From<SinkError> for SendError<()> {
+    fn from(error: SinkError) -> Self {
+        match error {
+            SinkError::ReceiverDropped => SendError::ReceiverDropped(()),
+            SinkError::Closed => SendError::Closed(()),
+            SinkError::Full => SendError::Full(()),
+        }
+    }
+}
+
+/// the sending end of any queue in this crate, for code that should work with all of
+/// them. The trait is object safe, so it can be used as `dyn Producer<T>`.
+pub trait Producer<T> {
+    /// send a value without waiting, handing it back if it can't be sent
+    fn try_send(&self, value: T) -> Result<(), SendError<T>>;
+
+    /// the number of values the queue holds before sending fails or drops values, or
+    /// `None` if there is no limit
+    fn capacity(&self) -> Option<usize>;
+
+    /// true if nobody is going to receive values anymore
+    fn is_cancelled(&self) -> bool;
+}
+
+/// the receiving end of a queue where every value is received exactly once, so not
+/// implemented for `watch` and `broadcast`. Object safe, like `Producer`.
+pub trait Consumer<T> {
+    /// take the next value without waiting
+    fn try_recv(&mut self) -> Result<T, TryRecvError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// the queue is empty, but there are still senders
+    Empty,
+    /// the queue is empty and all senders are gone
+    Disconnected,
+}
+
+#[cfg(feature = "std")]
+impl error::Error for TryRecvError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecvError {
+    /// the queue is empty and all senders are gone
+    Disconnected,
+}
+
+#[cfg(feature = "std")]
+impl error::Error for RecvError {}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Disconnected => write!(f, "Disconnected"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// no value arrived before the timeout
+    Timeout,
+    /// the queue is empty and all senders are gone
+    Disconnected,
+}
+
+#[cfg(feature = "std")]
+impl error::Error for RecvTimeoutError {}
+
+#[cfg(feature = "std")]
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => write!(f, "Timeout"),
+            RecvTimeoutError::Disconnected => write!(f, "Disconnected"),
+        }
+    }
+}
+
+/// no value arrived within the idle timeout, see `Receiver::with_idle_timeout`
+#[cfg(feature = "timer")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Idle;
+
+#[cfg(feature = "timer")]
+impl error::Error for Idle {}
+
+#[cfg(feature = "timer")]
+impl fmt::Display for Idle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Idle")
+    }
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "Empty"),
+            TryRecvError::Disconnected => write!(f, "Disconnected"),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// cumulative counters of the queue, all 0 if they are not collected
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> QueueMetrics {
+        self.0.lock().metrics.unwrap_or_default()
+    }
+
+    /// current queue len. This can be used to detect when the receiver is lagging.
+    ///
+    /// This does not take the lock, so it is cheap to call in a loop.
+    pub fn queue_len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// true if the receiver has taken everything out of the queue, e.g. to decide when
+    /// to send the next batch. This takes the lock, so it is exact
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().queue.is_empty()
+    }
+
+    /// the largest queue len so far. Like `queue_len`, this does not take the lock
+    pub fn high_water_mark(&self) -> usize {
+        self.0
+            .high_water_mark
+            .load(Ordering::Relaxed)
+            .max(self.0.len())
+    }
+
+    /// number of live senders, including this one
+    pub fn sender_count(&self) -> usize {
+        self.0.lock().sender_count
+    }
+
+    /// the capacity of a bounded queue, or `None` if the queue is unbounded
+    pub fn capacity(&self) -> Option<usize> {
+        self.0.lock().capacity
+    }
+
+    /// how many more values fit into a bounded queue right now, or `None` if the queue
+    /// is unbounded. Slots claimed by a `Permit` are not available
+    pub fn remaining_capacity(&self) -> Option<usize> {
+        let inner = self.0.lock();
+        inner
+            .capacity
+            .map(|capacity| capacity.saturating_sub(inner.queue.len() + inner.reserved))
+    }
+
+    // true if the receiver is dropped, and therefore there is no point in sending anymore.
+    // this does not take the lock
+    pub fn is_cancelled(&self) -> bool {
+        self.0.receiver_dropped.load(Ordering::Relaxed)
+    }
+
+    /// true if the receiver is parked waiting for a value, for testing wakeups
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn has_waker(&self) -> bool {
+        self.0.lock().waker.is_some()
+    }
+
+    /// send a value, returning the queue len after the send.
+    ///
+    /// On a bounded queue that is at capacity, this will fail with `SendError::Full` and
+    /// hand back the value, unless the overflow policy allows dropping values. Use
+    /// `send_with_outcome` to find out if something was dropped.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        #[cfg(feature = "crossbeam")]
+        let value = match self.0.try_push(value) {
+            Ok(len) => return Ok(len),
+            Err(value) => value,
+        };
+        let mut inner = self.0.lock();
+        inner.send(value).map(|_| inner.queue.len())
+    }
+
+    /// send a clone of a value, returning the queue len after the send.
+    ///
+    /// The value is only cloned once it is clear that it can be queued, so nothing is
+    /// cloned if the receiver is gone, the queue is closed, the queue is full, or the
+    /// overflow policy would drop the clone right away. A weighted queue weighs the
+    /// value by reference before cloning it.
+    pub fn send_ref(&self, value: &T) -> Result<usize, SendError<()>>
+    where
+        T: Clone,
+    {
+        let mut inner = self.0.lock();
+        if inner.receiver_dropped {
+            return Err(SendError::ReceiverDropped(()));
+        } else if inner.closed {
+            return Err(SendError::Closed(()));
+        } else if !inner.has_room() || !inner.fits(inner.weigh(value)) {
+            match inner.policy {
+                OverflowPolicy::Block | OverflowPolicy::Error => {
+                    return Err(SendError::Full(()));
+                }
+                OverflowPolicy::DropNewest => {
+                    // same as offer, which would drop the clone and count it
+                    inner.next_seq += 1;
+                    return Ok(inner.queue.len());
+                }
+                OverflowPolicy::DropOldest => {}
+            }
+        }
+        inner
+            .offer(value.clone())
+            .map(|_| inner.queue.len())
+            .map_err(|_| SendError::Full(()))
+    }
+
+    /// send a value, returning its sequence number.
+    ///
+    /// Every value that is sent to the queue gets the next number, no matter which
+    /// sender or send method it came from, starting with 0. Values that are dropped by
+    /// the overflow policy get a number as well, so drops show up as gaps.
+    pub fn send_seq(&self, value: T) -> Result<u64, SendError<T>> {
+        let mut inner = self.0.lock();
+        inner.send(value).map(|_| inner.next_seq - 1)
+    }
+
+    /// send a value without waiting, handing it back if it can not be sent.
+    ///
+    /// Returns the queue len after the send. `TrySendError::Full` is only returned for a
+    /// bounded queue that is at capacity and does not drop values.
+    pub fn try_send(&self, value: T) -> Result<usize, TrySendError<T>> {
+        #[cfg(feature = "crossbeam")]
+        let value = match self.0.try_push(value) {
+            Ok(len) => return Ok(len),
+            Err(value) => value,
+        };
+        let mut inner = self.0.lock();
+        if inner.receiver_dropped || inner.closed {
+            return Err(TrySendError::Disconnected(value));
+        }
+        match inner.offer(value) {
+            Ok(_) => Ok(inner.queue.len()),
+            Err(value) => Err(TrySendError::Full(value)),
+        }
+    }
+
+    /// send a value only if `pred` accepts the current queue len.
+    ///
+    /// The check and the send happen under the same lock, so unlike calling `queue_len`
+    /// before `send` there is no race with other senders. Returns `None` and drops the
+    /// value if `pred` returns false.
+    pub fn send_if(
+        &self,
+        value: T,
+        pred: impl FnOnce(usize) -> bool,
+    ) -> Result<Option<usize>, SendError<T>> {
+        let mut inner = self.0.lock();
+        // if the queue is not open, send will report the error
+        if inner.receiver_dropped || inner.closed || pred(inner.queue.len()) {
+            inner.send(value).map(|_| Some(inner.queue.len()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// send a value, with high priority values going before all normal ones.
+    ///
+    /// Values of the same priority are received in the order they were sent, and a high
+    /// priority value is received before all normal values that are still queued.
+    /// Otherwise this is the same as `send`, including the capacity limit.
+    pub fn send_priority(&self, value: T, high: bool) -> Result<usize, SendError<T>> {
+        let mut inner = self.0.lock();
+        if inner.receiver_dropped {
+            Err(SendError::ReceiverDropped(value))
+        } else if inner.closed {
+            Err(SendError::Closed(value))
+        } else {
+            let result = inner.offer_at(value, high).map_err(SendError::Full);
+            result.map(|_| inner.queue.len())
+        }
+    }
+
+    /// send a value, unless it is equal to the last value that is still queued.
+    ///
+    /// This collapses repeated notifications without waking the receiver again. The
+    /// comparison and the send happen under the same lock.
+    pub fn send_dedup(&self, value: T) -> Result<SendOutcome<T>, SendError<T>>
+    where
+        T: PartialEq,
+    {
+        let mut inner = self.0.lock();
+        if !inner.receiver_dropped && !inner.closed && inner.queue.back() == Some(&value) {
+            Ok(SendOutcome::Duplicate(value))
+        } else {
+            inner.send(value)
+        }
+    }
+
+    /// send a value, returning how full the queue is compared to its thresholds
+    pub fn send_with_backpressure_hint(&self, value: T) -> Result<BackpressureLevel, SendError<T>> {
+        let mut inner = self.0.lock();
+        inner.send(value)?;
+        let len = inner.queue.len();
+        Ok(match inner.thresholds {
+            Some((_, critical)) if len >= critical => BackpressureLevel::Critical,
+            Some((elevated, _)) if len >= elevated => BackpressureLevel::Elevated,
+            _ => BackpressureLevel::Ok,
+        })
+    }
+
+    /// send a value, returning what happened according to the overflow policy
+    pub fn send_with_outcome(&self, value: T) -> Result<SendOutcome<T>, SendError<T>> {
+        self.0.lock().send(value)
+    }
+
+    /// send all values under a single lock, waking the receiver only once.
+    ///
+    /// Returns the queue len after the send. If the receiver is gone or the queue is
+    /// closed, nothing is sent and all values are handed back. On a bounded queue, as many
+    /// values as fit are sent and the rest is handed back in `SendError::Full`.
+    pub fn send_all<I: IntoIterator<Item = T>>(
+        &self,
+        values: I,
+    ) -> Result<usize, SendError<Vec<T>>> {
+        let mut inner = self.0.lock();
+        if inner.receiver_dropped {
+            return Err(SendError::ReceiverDropped(values.into_iter().collect()));
+        } else if inner.closed {
+            return Err(SendError::Closed(values.into_iter().collect()));
+        }
+        let mut values = values.into_iter();
+        let mut rest = Vec::new();
+        // the first push takes the receiver waker, so it is only woken once
+        for value in &mut values {
+            if let Err(value) = inner.offer(value) {
+                rest.push(value);
+                break;
+            }
+        }
+        rest.extend(values);
+        if rest.is_empty() {
+            Ok(inner.queue.len())
+        } else {
+            Err(SendError::Full(rest))
+        }
+    }
+
+    /// send a value, blocking the thread until there is room or `timeout` has passed.
+    ///
+    /// The send-side counterpart of `Receiver::recv_timeout`. If the receiver is dropped
+    /// or the queue is closed while waiting, this fails right away. A queue with
+    /// `OverflowPolicy::Error` does not wait, so a full queue is a timeout right away.
+    #[cfg(feature = "std")]
+    pub fn send_timeout(&self, value: T, timeout: Duration) -> Result<usize, SendTimeoutError<T>> {
+        let deadline = Instant::now() + timeout;
+        let waker = task::waker(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut send = self.send_async(value);
+        loop {
+            match Pin::new(&mut send).poll(&mut cx) {
+                Poll::Ready(Ok(len)) => break Ok(len),
+                Poll::Ready(Err(SendError::Full(value))) => {
+                    break Err(SendTimeoutError::Timeout(value))
+                }
+                Poll::Ready(Err(cause)) => {
+                    break Err(SendTimeoutError::Disconnected(cause.into_inner()))
+                }
+                Poll::Pending => {
+                    // recompute the remaining time, so spurious unparks don't extend the wait
+                    let now = Instant::now();
+                    if now >= deadline {
+                        // dropping the future removes our waker from the queue
+                        let value = send
+                            .value
+                            .take()
+                            .expect("value is only taken on completion");
+                        break Err(SendTimeoutError::Timeout(value));
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        }
+    }
+
+    /// send all values or none of them, under a single lock.
+    ///
+    /// Returns the queue len after the send. If a bounded queue does not have room for
+    /// all values, nothing is sent and all values are handed back in `SendError::Full`,
+    /// whatever the overflow policy is.
+    pub fn send_batch_atomic<I: IntoIterator<Item = T>>(
+        &self,
+        values: I,
+    ) -> Result<usize, SendError<Vec<T>>> {
+        let values = values.into_iter().collect::<Vec<_>>();
+        let mut inner = self.0.lock();
+        if inner.receiver_dropped {
+            return Err(SendError::ReceiverDropped(values));
+        } else if inner.closed {
+            return Err(SendError::Closed(values));
+        } else if inner.free_slots() < values.len()
+            || !inner.fits(values.iter().map(|value| inner.weigh(value)).sum())
+        {
+            return Err(SendError::Full(values));
+        }
+        for value in values {
+            inner.push(value);
+        }
+        Ok(inner.queue.len())
+    }
+
+    /// send a value, waiting for the receiver to make room if the queue is bounded.
+    ///
+    /// The future is cancel safe: if it is dropped before completion, the value is not sent.
+    pub fn send_async(&self, value: T) -> SendAsync<'_, T> {
+        SendAsync {
+            sender: self,
+            value: Some(value),
+            key: None,
+        }
+    }
+
+    /// close the queue for all senders, without having to drop them.
+    ///
+    /// The receiver will still get the values that are already queued, and then see the
+    /// end of the stream. Sending after this fails with `SendError::Closed`.
+    pub fn close(&self) {
+        self.0.lock().close(CloseReason::Closed);
+    }
+
+    /// wake the receiver without sending anything, e.g. so it can check some state that
+    /// is shared outside of the queue. Does nothing if the receiver is not waiting.
+    pub fn notify(&self) {
+        self.0.lock().wake_receiver();
+    }
+
+    /// close the queue because something went wrong.
+    ///
+    /// This is like `close`, but the receiver sees `CloseReason::Aborted`, so it can tell
+    /// that the values it got are incomplete.
+    pub fn abort(&self) {
+        self.0.lock().close(CloseReason::Aborted);
+    }
+
+    /// close the queue, and wait until the receiver has taken all queued values.
+    ///
+    /// The queue is closed right away, not when the future is first polled. The future
+    /// also resolves when the receiver is dropped, since then nothing is left to drain.
+    pub fn close_and_drain(self) -> CloseAndDrain<T> {
+        self.close();
+        CloseAndDrain {
+            sender: self,
+            key: None,
+        }
+    }
+
+    /// a future that resolves once there is no point in sending anymore, because the
+    /// receiver is dropped or the queue is closed.
+    ///
+    /// Useful to abort expensive work for values that nobody is going to receive.
+    pub fn closed(&self) -> Closed<'_, T> {
+        Closed {
+            sender: self,
+            key: None,
+        }
+    }
+
+    /// wait for room in the queue, and claim it for a value that is sent later.
+    ///
+    /// This allows checking for room before doing the work to produce a value. Only
+    /// waits with `OverflowPolicy::Block`, with the other policies this fails with
+    /// `SendError::Full` if there is no room. The future is cancel safe.
+    pub fn reserve(&self) -> Reserve<'_, T> {
+        Reserve {
+            sender: self,
+            key: None,
+        }
+    }
+
+    /// claim a slot in the queue without waiting, moving the sender into the permit.
+    ///
+    /// Unlike the `Permit` from `reserve`, the `OwnedPermit` does not borrow the sender,
+    /// so it can be moved into a spawned task. On failure the sender is handed back
+    /// together with the reason.
+    pub fn try_reserve_owned(self) -> Result<OwnedPermit<T>, (Sender<T>, TrySendError<()>)> {
+        let mut inner = self.0.lock();
+        if inner.receiver_dropped || inner.closed {
+            drop(inner);
+            Err((self, TrySendError::Disconnected(())))
+        } else if inner.has_room() {
+            inner.claim_permit();
+            drop(inner);
+            Ok(OwnedPermit { sender: Some(self) })
+        } else {
+            drop(inner);
+            Err((self, TrySendError::Full(())))
+        }
+    }
+
+    pub fn sink(self) -> Sink<T> {
+        Sink {
+            sender: Some(self),
+            ready_key: None,
+            flush_key: None,
+            slots: 0,
+        }
+    }
+
+    /// a sender of `U`s, which are converted using `f` before they are queued.
+    ///
+    /// `f` is not called for values that can't be sent because the receiver is gone or
+    /// the queue is closed.
+    pub fn with<U, F: Fn(U) -> T>(self, f: F) -> MappedSender<T, F> {
+        MappedSender { sender: self, f }
+    }
+
+    /// a handle that can be turned back into a sender, but does not keep the queue open
+    pub fn downgrade(&self) -> WeakSender<T> {
+        WeakSender(Arc::downgrade(&self.0))
+    }
+}
+
+/// a sender that does not count as a sender, see `Sender::downgrade`.
+///
+/// The receiver ends once all `Sender`s are gone, no matter how many of these are left.
+pub struct WeakSender<T>(Weak<Shared<T>>);
+
+impl<T> WeakSender<T> {
+    /// get a sender back, unless all senders are gone already
+    pub fn upgrade(&self) -> Option<Sender<T>> {
+        let inner = self.0.upgrade()?;
+        {
+            let mut guard = inner.lock();
+            if guard.sender_count == 0 {
+                return None;
+            }
+            guard.sender_count += 1;
+        }
+        Some(Sender(inner))
+    }
+}
+
+impl<T> Clone for WeakSender<T> {
+    fn clone(&self) -> Self {
+        WeakSender(self.0.clone())
+    }
+}
+
+/// a type erased stream that still is a `FusedStream`, see `Receiver::boxed`
+pub type BoxFusedStream<T> = Pin<Box<dyn FusedStream<Item = T> + Send>>;
+
+/// a sender that stamps values with the time they were queued, see `mpsc_timed`
+#[cfg(feature = "std")]
+pub struct TimedSender<T>(Sender<(Instant, T)>);
+
+// a derive would require T: Clone
+#[cfg(feature = "std")]
+impl<T> Clone for TimedSender<T> {
+    fn clone(&self) -> Self {
+        TimedSender(self.0.clone())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> TimedSender<T> {
+    // true if the receiver is dropped, and therefore there is no point in sending anymore
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+
+    pub fn into_inner(self) -> Sender<(Instant, T)> {
+        self.0
+    }
+
+    /// stamp and send a value, returning the queue len after the send.
+    ///
+    /// The time is taken while the queue is locked, so the stamps of all senders are in
+    /// queue order.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut inner = self.0 .0.lock();
+        match inner.send((Instant::now(), value)) {
+            Ok(_) => Ok(inner.queue.len()),
+            Err(error) => Err(error.map(|(_, value)| value)),
+        }
+    }
+}
+
+/// a sender that converts values before sending them, see `Sender::with`
+pub struct MappedSender<T, F> {
+    sender: Sender<T>,
+    f: F,
+}
+
+// a derive would require T: Clone
+impl<T, F: Clone> Clone for MappedSender<T, F> {
+    fn clone(&self) -> Self {
+        MappedSender {
+            sender: self.sender.clone(),
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<T, F> MappedSender<T, F> {
+    // true if the receiver is dropped, and therefore there is no point in sending anymore
+    pub fn is_cancelled(&self) -> bool {
+        self.sender.is_cancelled()
+    }
+
+    pub fn into_inner(self) -> Sender<T> {
+        self.sender
+    }
+
+    pub fn sink(self) -> MappedSink<T, F> {
+        MappedSink {
+            sink: self.sender.sink(),
+            f: self.f,
+        }
+    }
+
+    /// convert and send a value, returning the queue len after the send.
+    ///
+    /// The room for the value is claimed before it is converted, and `f` is called
+    /// without holding the lock, so a slow or panicking `f` can't block the queue. A value
+    /// that can't be sent is handed back unconverted. If the receiver goes away while `f`
+    /// runs, the converted value is dropped.
+    pub fn send<U>(&self, value: U) -> Result<usize, SendError<U>>
+    where
+        F: Fn(U) -> T,
+    {
+        let mut inner = self.sender.0.lock();
+        if inner.receiver_dropped {
+            return Err(SendError::ReceiverDropped(value));
+        } else if inner.closed {
+            return Err(SendError::Closed(value));
+        }
+        // make room the same way as `QueueInner::offer`
+        let mut oldest = None;
+        if !inner.has_room() {
+            match inner.policy {
+                OverflowPolicy::Block | OverflowPolicy::Error => {
+                    return Err(SendError::Full(value));
+                }
+                OverflowPolicy::DropOldest => oldest = inner.evict_oldest(false),
+                OverflowPolicy::DropNewest => {}
+            }
+            if oldest.is_none() {
+                // the value would be dropped anyway, so there is no need to convert it
+                inner.next_seq += 1;
+                let len = inner.queue.len();
+                drop(inner);
+                drop(value);
+                return Ok(len);
+            }
+        }
+        inner.claim_permit();
+        drop(inner);
+        // values are dropped outside of the lock
+        drop(oldest);
+        // if f panics, dropping the permit gives the slot back
+        let permit = Permit {
+            sender: &self.sender,
+        };
+        Ok(permit.push((self.f)(value)))
+    }
+}
+
+/// a `futures::sink::Sink` that converts values before sending them
+pub struct MappedSink<T, F> {
+    sink: Sink<T>,
+    f: F,
+}
+
+// f is never pinned
+impl<T, F> Unpin for MappedSink<T, F> {}
+
+impl<T, U, F: Fn(U) -> T> futures::sink::Sink<U> for MappedSink<T, F> {
+    type Error = SinkError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: U) -> Result<(), Self::Error> {
+        if let Some(sender) = &self.sink.sender {
+            if sender.is_cancelled() {
+                return Err(SinkError::ReceiverDropped);
+            }
+        }
+        let item = (self.f)(item);
+        Pin::new(&mut self.sink).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_close(cx)
+    }
+}
+
+/// helpers for queues of byte chunks
+#[cfg(feature = "bytes")]
+impl Sender<bytes::Bytes> {
+    /// send a copy of `bytes` as a single chunk, returning the queue len after the send
+    pub fn send_slice(&self, bytes: &[u8]) -> Result<usize, SendError<bytes::Bytes>> {
+        self.send(bytes::Bytes::copy_from_slice(bytes))
+    }
+}
+
+impl<T> Producer<T> for Sender<T> {
+    fn try_send(&self, value: T) -> Result<(), SendError<T>> {
+        self.send(value).map(|_| ())
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Sender::capacity(self)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        Sender::is_cancelled(self)
+    }
+}
+
+/// sends all values under a single lock, like `Sender::send_all`.
+///
+/// Since `extend` can't fail, values that can't be sent because the receiver is gone,
+/// the queue is closed or a bounded queue is full, are silently dropped.
+impl<T> Extend<T> for Sender<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let _ = self.send_all(iter);
+    }
+}
+
+/// future returned by `Sender::send_async`
+pub struct SendAsync<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+    key: Option<usize>,
+}
+
+// the value is never pinned, we just move it into the queue
+impl<T> Unpin for SendAsync<'_, T> {}
+
+impl<T> Future for SendAsync<'_, T> {
+    type Output = Result<usize, SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut inner = this.sender.0.lock();
+        if inner.receiver_dropped || inner.closed {
+            inner.sender_wakers.remove(this.key.take());
+            let value = this
+                .value
+                .take()
+                .expect("SendAsync polled after completion");
+            Poll::Ready(Err(if inner.receiver_dropped {
+                SendError::ReceiverDropped(value)
+            } else {
+                SendError::Closed(value)
+            }))
+        } else if this
+            .value
+            .as_ref()
+            .is_some_and(|value| inner.must_wait_for(value))
+        {
+            inner.sender_wakers.register(&mut this.key, cx.waker());
+            Poll::Pending
+        } else {
+            inner.sender_wakers.remove(this.key.take());
+            let value = this
+                .value
+                .take()
+                .expect("SendAsync polled after completion");
+            let result = inner.offer(value).map_err(SendError::Full);
+            Poll::Ready(result.map(|_| inner.queue.len()))
+        }
+    }
+}
+
+impl<T> Drop for SendAsync<'_, T> {
+    fn drop(&mut self) {
+        if self.key.is_some() {
+            let mut inner = self.sender.0.lock();
+            inner.unpark_sender(self.key.take());
+        }
+    }
+}
+
+/// future returned by `Sender::reserve`
+pub struct Reserve<'a, T> {
+    sender: &'a Sender<T>,
+    key: Option<usize>,
+}
+
+impl<'a, T> Future for Reserve<'a, T> {
+    type Output = Result<Permit<'a, T>, SendError<()>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut inner = this.sender.0.lock();
+        if inner.receiver_dropped {
+            inner.sender_wakers.remove(this.key.take());
+            Poll::Ready(Err(SendError::ReceiverDropped(())))
+        } else if inner.closed {
+            inner.sender_wakers.remove(this.key.take());
+            Poll::Ready(Err(SendError::Closed(())))
+        } else if inner.must_wait() {
+            inner.sender_wakers.register(&mut this.key, cx.waker());
+            Poll::Pending
+        } else {
+            inner.sender_wakers.remove(this.key.take());
+            if inner.has_room() {
+                inner.claim_permit();
+                Poll::Ready(Ok(Permit {
+                    sender: this.sender,
+                }))
+            } else {
+                Poll::Ready(Err(SendError::Full(())))
+            }
+        }
+    }
+}
+
+impl<T> Drop for Reserve<'_, T> {
+    fn drop(&mut self) {
+        if self.key.is_some() {
+            let mut inner = self.sender.0.lock();
+            inner.unpark_sender(self.key.take());
+        }
+    }
+}
+
+/// a claimed slot in the queue, see `Sender::reserve`.
+///
+/// Dropping the permit without sending gives the slot back. A permit can still be used
+/// after the queue is closed, and the stream of the receiver only ends once all permits
+/// are used or dropped.
+pub struct Permit<'a, T> {
+    sender: &'a Sender<T>,
+}
+
+impl<T> Permit<'_, T> {
+    /// send a value into the claimed slot, without waiting.
+    ///
+    /// If the receiver is gone in the meantime, the value is dropped.
+    pub fn send(self, value: T) {
+        self.push(value);
+    }
+
+    /// send the value like `send`, returning the queue len
+    fn push(self, value: T) -> usize {
+        let mut inner = self.sender.0.lock();
+        inner.release_permit();
+        if !inner.receiver_dropped {
+            inner.push(value);
+        }
+        let len = inner.queue.len();
+        drop(inner);
+        // the slot is used, so there is nothing for drop to do
+        mem::forget(self);
+        len
+    }
+}
+
+impl<T> Drop for Permit<'_, T> {
+    fn drop(&mut self) {
+        let mut inner = self.sender.0.lock();
+        inner.release_permit();
+        // the slot is free again
+        inner.sender_wakers.wake(1);
+    }
+}
+
+/// a claimed slot in the queue that owns its sender, see `Sender::try_reserve_owned`.
+///
+/// Dropping the permit without sending gives the slot back. Like a `Permit`, it can
+/// still be used after the queue is closed.
+pub struct OwnedPermit<T> {
+    /// only `None` once the permit is used
+    sender: Option<Sender<T>>,
+}
+
+impl<T> OwnedPermit<T> {
+    /// send a value into the claimed slot without waiting, and get the sender back.
+    ///
+    /// If the receiver is gone in the meantime, the value is dropped.
+    pub fn send(mut self, value: T) -> Sender<T> {
+        let sender = self.sender.take().expect("permit already used");
+        let mut inner = sender.0.lock();
+        inner.release_permit();
+        if !inner.receiver_dropped {
+            inner.push(value);
+        }
+        drop(inner);
+        sender
+    }
+}
+
+impl<T> Drop for OwnedPermit<T> {
+    fn drop(&mut self) {
+        if let Some(sender) = &self.sender {
+            let mut inner = sender.0.lock();
+            inner.release_permit();
+            // the slot is free again
+            inner.sender_wakers.wake(1);
+        }
+    }
+}
+
+/// future returned by `Receiver::all_senders_dropped`
+pub struct AllSendersDropped<'a, T> {
+    receiver: &'a mut Receiver<T>,
+    /// true if the last poll left our waker in the queue
+    parked: bool,
+}
+
+impl<T> Future for AllSendersDropped<'_, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut inner = this.receiver.0.lock();
+        this.parked = inner.sender_count > 0;
+        if this.parked {
+            inner.senders_dropped_waker = Some(cx.waker().clone());
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+impl<T> Drop for AllSendersDropped<'_, T> {
+    fn drop(&mut self) {
+        if self.parked {
+            self.receiver.0.lock().senders_dropped_waker = None;
+        }
+    }
+}
+
+/// future returned by `Receiver::wait_for`
+pub struct WaitFor<'a, T, P> {
+    receiver: &'a mut Receiver<T>,
+    pred: P,
+    /// true if the last poll left our waker in the queue
+    parked: bool,
+}
+
+// pred is never pinned
+impl<T, P> Unpin for WaitFor<'_, T, P> {}
+
+impl<T, P: FnMut(&T) -> bool> Future for WaitFor<'_, T, P> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut discarded = Vec::new();
+        let mut inner = this.receiver.0.lock();
+        if inner.park_if_paused(cx.waker()) {
+            this.parked = true;
+            return Poll::Pending;
+        }
+        let found = loop {
+            match inner.pop_front() {
+                Some(value) if (this.pred)(&value) => break Some(value),
+                Some(value) => discarded.push(value),
+                None => break None,
+            }
+        };
+        let n = discarded.len() + found.is_some() as usize;
+        if n > 0 {
+            inner.popped(n);
+        }
+        this.parked = found.is_none() && !inner.senders_done();
+        if this.parked {
+            inner.park_receiver(cx.waker());
+        }
+        // values might hold senders, so don't drop them under the lock
+        drop(inner);
+        drop(discarded);
+        if this.parked {
+            Poll::Pending
+        } else {
+            Poll::Ready(found)
+        }
+    }
+}
+
+impl<T, P> Drop for WaitFor<'_, T, P> {
+    fn drop(&mut self) {
+        if self.parked {
+            self.receiver.0.lock().waker = None;
+        }
+    }
+}
+
+/// future returned by `Receiver::recv_or_closed`
+pub struct RecvOrClosed<'a, T>(Recv<'a, T>);
+
+impl<T> Future for RecvOrClosed<'_, T> {
+    type Output = RecvResult<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.0).poll(cx) {
+            Poll::Ready(Some(value)) => Poll::Ready(RecvResult::Value(value)),
+            Poll::Ready(None) => Poll::Ready(RecvResult::Closed),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// future returned by `Receiver::recv_async`
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+    /// true if the last poll left our waker in the queue
+    parked: bool,
+}
+
+impl<T> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let result = Pin::new(&mut *this.receiver).poll_next(cx);
+        this.parked = result.is_pending();
+        result
+    }
+}
+
+impl<T> Drop for Recv<'_, T> {
+    fn drop(&mut self) {
+        if self.parked {
+            self.receiver.0.lock().waker = None;
+        }
+    }
+}
+
+/// stream returned by `Receiver::into_chunks`
+pub struct Chunks<T> {
+    receiver: Receiver<T>,
+    /// the values of a chunk that is not complete yet
+    buf: Vec<T>,
+    n: usize,
+}
+
+// the buffered values are never pinned
+impl<T> Unpin for Chunks<T> {}
+
+impl<T> Stream for Chunks<T> {
+    type Item = Vec<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let mut inner = this.receiver.0.lock();
+        if inner.park_if_paused(cx.waker()) {
+            return Poll::Pending;
+        }
+        let missing = this.n - this.buf.len();
+        inner.pop_many(&mut this.buf, missing);
+        if this.buf.len() == this.n {
+            Poll::Ready(Some(mem::replace(
+                &mut this.buf,
+                Vec::with_capacity(this.n),
+            )))
+        } else if inner.senders_done() {
+            if this.buf.is_empty() {
+                Poll::Ready(None)
+            } else {
+                Poll::Ready(Some(mem::take(&mut this.buf)))
+            }
+        } else {
+            inner.park_receiver(cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+/// iterator returned by `Receiver::try_iter`
+pub struct TryIter<'a, T>(&'a mut Receiver<T>);
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.try_recv().ok()
+    }
+}
+
+/// blocking iterator over all values, see `IntoIterator for Receiver`
+#[cfg(feature = "std")]
+pub struct IntoIter<T>(Receiver<T>);
+
+#[cfg(feature = "std")]
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.recv().ok()
+    }
+}
+
+/// iterate over all values, blocking the current thread using `Receiver::recv` until
+/// all senders are gone
+#[cfg(feature = "std")]
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(self)
+    }
+}
+
+/// stream returned by `Receiver::debounce`
+#[cfg(feature = "timer")]
+pub struct Debounce<T> {
+    receiver: Receiver<T>,
+    window: Duration,
+    /// the latest value of the current window
+    latest: Option<T>,
+    /// running while we have a value
+    delay: Option<futures_timer::Delay>,
+}
+
+// the latest value is never pinned, and the delay is Unpin
+#[cfg(feature = "timer")]
+impl<T> Unpin for Debounce<T> {}
+
+#[cfg(feature = "timer")]
+impl<T> Stream for Debounce<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            match Pin::new(&mut this.receiver).poll_next(cx) {
+                Poll::Ready(Some(value)) => {
+                    this.latest = Some(value);
+                    if this.delay.is_none() {
+                        this.delay = Some(futures_timer::Delay::new(this.window));
+                    }
+                }
+                Poll::Ready(None) => {
+                    this.delay = None;
+                    return Poll::Ready(this.latest.take());
+                }
+                Poll::Pending => break,
+            }
+        }
+        match &mut this.delay {
+            Some(delay) => match Pin::new(delay).poll(cx) {
+                Poll::Ready(()) => {
+                    this.delay = None;
+                    Poll::Ready(this.latest.take())
+                }
+                Poll::Pending => Poll::Pending,
+            },
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// stream returned by `Receiver::with_idle_timeout`
+#[cfg(feature = "timer")]
+pub struct IdleTimeout<T> {
+    receiver: Receiver<T>,
+    timeout: Duration,
+    /// restarted whenever something is yielded
+    delay: futures_timer::Delay,
+}
+
+#[cfg(feature = "timer")]
+impl<T> Stream for IdleTimeout<T> {
+    type Item = Result<T, Idle>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.receiver).poll_next(cx) {
+            Poll::Ready(Some(value)) => {
+                this.delay = futures_timer::Delay::new(this.timeout);
+                Poll::Ready(Some(Ok(value)))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => match Pin::new(&mut this.delay).poll(cx) {
+                Poll::Ready(()) => {
+                    this.delay = futures_timer::Delay::new(this.timeout);
+                    Poll::Ready(Some(Err(Idle)))
+                }
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[cfg(feature = "timer")]
+impl<T> FusedStream for IdleTimeout<T> {
+    fn is_terminated(&self) -> bool {
+        self.receiver.is_terminated()
+    }
+}
+
+/// stream returned by `Receiver::map`
+pub struct Map<T, F> {
+    receiver: Receiver<T>,
+    f: F,
+}
+
+// f is never pinned
+impl<T, F> Unpin for Map<T, F> {}
+
+impl<T, U, F: FnMut(T) -> U> Stream for Map<T, F> {
+    type Item = U;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.receiver).poll_next(cx) {
+            Poll::Ready(value) => Poll::Ready(value.map(&mut this.f)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T, U, F: FnMut(T) -> U> FusedStream for Map<T, F> {
+    fn is_terminated(&self) -> bool {
+        self.receiver.is_terminated()
+    }
+}
+
+/// stream returned by `Receiver::filter`
+pub struct Filter<T, F> {
+    receiver: Receiver<T>,
+    pred: F,
+}
+
+// pred is never pinned
+impl<T, F> Unpin for Filter<T, F> {}
+
+impl<T, F: FnMut(&T) -> bool> Stream for Filter<T, F> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            match Pin::new(&mut this.receiver).poll_next(cx) {
+                Poll::Ready(Some(value)) if !(this.pred)(&value) => continue,
+                other => return other,
+            }
+        }
+    }
+}
+
+impl<T, F: FnMut(&T) -> bool> FusedStream for Filter<T, F> {
+    fn is_terminated(&self) -> bool {
+        self.receiver.is_terminated()
+    }
+}
+
+/// stream returned by `Receiver::with_depth`
+pub struct WithDepth<T>(Receiver<T>);
+
+impl<T> Stream for WithDepth<T> {
+    type Item = (T, usize);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut inner = (self.0).0.lock();
+        if inner.park_if_paused(cx.waker()) {
+            return Poll::Pending;
+        }
+        if let Some(value) = inner.pop() {
+            Poll::Ready(Some((value, inner.queue.len())))
+        } else if inner.senders_done() {
+            Poll::Ready(None)
+        } else {
+            inner.park_receiver(cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> FusedStream for WithDepth<T> {
+    fn is_terminated(&self) -> bool {
+        self.0.is_terminated()
+    }
+}
+
+/// future returned by `Sender::closed`
+pub struct Closed<'a, T> {
+    sender: &'a Sender<T>,
+    key: Option<usize>,
+}
+
+impl<T> Future for Closed<'_, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut inner = this.sender.0.lock();
+        if inner.receiver_dropped || inner.closed {
+            inner.closed_wakers.remove(this.key.take());
+            Poll::Ready(())
+        } else {
+            inner.closed_wakers.register(&mut this.key, cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for Closed<'_, T> {
+    fn drop(&mut self) {
+        if self.key.is_some() {
+            self.sender.0.lock().closed_wakers.remove(self.key.take());
+        }
+    }
+}
+
+/// future returned by `Sender::close_and_drain`
+pub struct CloseAndDrain<T> {
+    sender: Sender<T>,
+    key: Option<usize>,
+}
+
+impl<T> Future for CloseAndDrain<T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut inner = this.sender.0.lock();
+        if inner.receiver_dropped || inner.queue.is_empty() {
+            inner.drain_wakers.remove(this.key.take());
+            Poll::Ready(())
+        } else {
+            inner.drain_wakers.register(&mut this.key, cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for CloseAndDrain<T> {
+    fn drop(&mut self) {
+        if self.key.is_some() {
+            self.sender.0.lock().drain_wakers.remove(self.key.take());
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // we might be the last sender, and the receiver might be waiting for us.
+        // this will cause some false wakeups, but that's ok.
+        let mut inner = self.0.lock();
+        inner.sender_count -= 1;
+        inner.observe(ChannelEvent::SenderDropped {
+            remaining: inner.sender_count,
+        });
+        if inner.sender_count == 0 {
+            inner
+                .close_reason
+                .get_or_insert(CloseReason::AllSendersDropped);
+            if let Some(waker) = inner.senders_dropped_waker.take() {
+                waker.wake();
+            }
+        }
+        inner.wake_receiver();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.0.lock();
+        inner.receiver_dropped = true;
+        inner.waker = None;
+        // nobody is ever going to get these, so drop them now instead of when the last
+        // sender goes away. Senders can't push anymore once they see receiver_dropped.
+        let queue = mem::take(&mut inner.queue);
+        inner.high_len = 0;
+        if let Some(weight) = &mut inner.weight {
+            weight.current = 0;
+        }
+        inner.observe(ChannelEvent::ReceiverDropped);
+        // parked senders need to find out that there is no point in waiting anymore
+        inner.wake_senders();
+        inner.drain_wakers.wake_all();
+        inner.closed_wakers.wake_all();
+        // the values might hold senders themselves, so drop them outside the lock
+        drop(inner);
+        drop(queue);
+        // sends can't skip the lock anymore, so nothing new arrives here
+        #[cfg(feature = "crossbeam")]
+        while self.0.incoming.pop().is_some() {}
+    }
+}
+
+/// the receiving end of a queue, which is a `Stream` of the values.
+///
+/// It is `Unpin` even if `T` is not, so it can be polled with `StreamExt::poll_next_unpin`
+/// and used with combinators that need `Unpin`.
+pub struct Receiver<T>(Arc<Shared<T>>);
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.0.lock();
+        f.debug_struct("Receiver")
+            .field("queue_len", &inner.queue.len())
+            .field("sender_count", &inner.sender_count)
+            .field("closed", &inner.closed)
+            .field("waiting", &inner.waker.is_some())
+            .finish()
+    }
+}
+
+/// wakes a thread that is blocked in `Receiver::recv`
+#[cfg(feature = "std")]
+struct ThreadWaker(thread::Thread);
+
+#[cfg(feature = "std")]
+impl ArcWake for ThreadWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.unpark();
+    }
+}
+
+impl<T> Receiver<T> {
+    /// cumulative counters of the queue, all 0 if they are not collected
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> QueueMetrics {
+        self.0.lock().metrics.unwrap_or_default()
+    }
+
+    /// true if the receiver is parked waiting for a value, for testing wakeups
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn has_waker(&self) -> bool {
+        self.0.lock().waker.is_some()
+    }
+
+    /// take all currently queued values under a single lock, for asserting the contents
+    /// of a queue in tests
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn drain_to_vec(&mut self) -> Vec<T> {
+        let mut inner = self.0.lock();
+        let n = inner.queue.len();
+        let mut values = Vec::with_capacity(n);
+        inner.pop_many(&mut values, n);
+        values
+    }
+
+    /// the capacity of a bounded queue, or `None` if the queue is unbounded
+    pub fn capacity(&self) -> Option<usize> {
+        self.0.lock().capacity
+    }
+
+    /// true if there is at least one live sender.
+    ///
+    /// Unlike the stream ending, this also tells apart "all senders are gone" from
+    /// "the queue is just empty right now".
+    pub fn is_connected(&self) -> bool {
+        self.0.lock().sender_count > 0
+    }
+
+    /// stop accepting new values, but keep the ones that are already queued.
+    ///
+    /// Sending after this fails with `SendError::Closed`, while the stream still yields
+    /// the queued values before it ends.
+    pub fn close(&mut self) {
+        self.0.lock().close(CloseReason::Closed);
+    }
+
+    /// why no more values can arrive, or `None` while they still can.
+    ///
+    /// Values that are already queued are still received, so once the stream has ended
+    /// this tells whether it ended normally.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.0.lock().close_reason
+    }
+
+    /// move the queued values from index `at` on into a new receiver, e.g. to hand them
+    /// to another worker.
+    ///
+    /// The new receiver has no senders, so its stream ends once it is drained. If `at`
+    /// is beyond the end of the queue, it is empty.
+    pub fn split_off(&mut self, at: usize) -> Receiver<T> {
+        let mut inner = self.0.lock();
+        let at = at.min(inner.queue.len());
+        let mut other = QueueInner::new(None, OverflowPolicy::Block);
+        other.queue = inner.queue.split_off(at);
+        for value in &other.queue {
+            inner.unweigh(value);
+        }
+        other.high_len = inner.high_len.saturating_sub(at);
+        other.sender_count = 0;
+        other.close_reason = Some(CloseReason::AllSendersDropped);
+        inner.high_len = inner.high_len.min(at);
+        // the moved values are not received, see `QueueMetrics`, but they still make room
+        let moved = other.queue.len();
+        #[cfg(feature = "metrics")]
+        {
+            other.metrics = inner.metrics.map(|_| QueueMetrics {
+                total_sent: moved as u64,
+                total_received: 0,
+                high_water_mark: moved,
+            });
+        }
+        inner.made_room(moved);
+        Receiver(Arc::new(Shared::new(other)))
+    }
+
+    /// remove all queued values for which `pred` returns true, e.g. to cancel queued
+    /// jobs, and return them in queue order.
+    ///
+    /// The remaining values keep their order. Senders that wait for room are woken.
+    pub fn drain_filter(&mut self, pred: impl Fn(&T) -> bool) -> Vec<T> {
+        let mut inner = self.0.lock();
+        let queue = mem::take(&mut inner.queue);
+        let mut removed = Vec::new();
+        let mut removed_high = 0;
+        for (index, value) in queue.into_iter().enumerate() {
+            if pred(&value) {
+                if index < inner.high_len {
+                    removed_high += 1;
+                }
+                inner.unweigh(&value);
+                removed.push(value);
+            } else {
+                inner.queue.push_back(value);
+            }
+        }
+        inner.high_len -= removed_high;
+        // like `split_off`, this is not receiving, see `QueueMetrics`
+        if !removed.is_empty() {
+            inner.made_room(removed.len());
+        }
+        removed
+    }
+
+    /// stop taking values out of the queue until `resume` is called.
+    ///
+    /// While paused, the stream stays pending even if values are queued, so a bounded
+    /// queue fills up and throttles the senders as usual. `try_recv` still works.
+    pub fn pause(&mut self) {
+        self.0.lock().paused = true;
+    }
+
+    /// undo `pause`, waking a task that is waiting for the stream
+    pub fn resume(&mut self) {
+        let mut inner = self.0.lock();
+        inner.paused = false;
+        inner.wake_receiver();
+    }
+
+    /// true if the receiver is paused
+    pub fn is_paused(&self) -> bool {
+        self.0.lock().paused
+    }
+
+    /// number of values currently queued
+    pub fn len(&self) -> usize {
+        self.0.lock().queue.len()
+    }
+
+    /// true if no values are currently queued
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().queue.is_empty()
+    }
+
+    /// a clone of all currently queued values, without removing them
+    pub fn snapshot(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        self.0.lock().queue.iter().cloned().collect()
+    }
+
+    /// run `f` on the next value without removing it from the queue.
+    ///
+    /// Returns `None` if the queue is empty. The lock is held while `f` runs, so keep it short.
+    pub fn with_front<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        self.0.lock().queue.front().map(f)
+    }
+
+    /// a clone of the next value, without removing it from the queue
+    pub fn peek(&self) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.with_front(T::clone)
+    }
+
+    /// take the next value without waiting, for use outside of an async context
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut inner = self.0.lock();
+        if let Some(value) = inner.pop() {
+            Ok(value)
+        } else if inner.senders_done() {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// run `f` on the next value, and take it out of the queue if `f` returns true.
+    ///
+    /// The decision happens under a single lock, so nobody else can take the value in
+    /// the meantime, e.g. to only remove a value once it is processed successfully.
+    pub fn try_recv_with<R>(&mut self, f: impl FnOnce(&T) -> (bool, R)) -> Result<R, TryRecvError> {
+        let mut inner = self.0.lock();
+        match inner.queue.front().map(f) {
+            Some((take, result)) => {
+                let value = if take { inner.pop() } else { None };
+                // don't drop the value under the lock
+                drop(inner);
+                drop(value);
+                Ok(result)
+            }
+            None if inner.senders_done() => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// take the first queued value that matches `pred`, dropping the ones before it.
+    ///
+    /// This happens under a single lock, e.g. to skip outdated values. Returns `None`
+    /// without waiting if no queued value matches, in which case all queued values are
+    /// dropped.
+    pub fn retain_recv(&mut self, mut pred: impl FnMut(&T) -> bool) -> Option<T> {
+        let mut discarded = Vec::new();
+        let mut inner = self.0.lock();
+        let found = loop {
+            match inner.pop_front() {
+                Some(value) if pred(&value) => break Some(value),
+                Some(value) => discarded.push(value),
+                None => break None,
+            }
+        };
+        let n = discarded.len() + found.is_some() as usize;
+        if n > 0 {
+            inner.popped(n);
+        }
+        // values might hold senders, so don't drop them under the lock
+        drop(inner);
+        drop(discarded);
+        found
+    }
+
+    /// wait for the next value that matches `pred`, or `None` once all senders are gone.
+    ///
+    /// Values that don't match are dropped, not put back into the queue, even if the
+    /// future is dropped before it completes. Each poll discards everything it can under
+    /// a single lock.
+    pub fn wait_for<P: FnMut(&T) -> bool>(&mut self, pred: P) -> WaitFor<'_, T, P> {
+        WaitFor {
+            receiver: self,
+            pred,
+            parked: false,
+        }
+    }
+
+    /// drop queued values from the front while `pred` returns true, returning how many
+    /// were dropped
+    pub fn discard_while(&mut self, mut pred: impl FnMut(&T) -> bool) -> usize {
+        let mut discarded = Vec::new();
+        let mut inner = self.0.lock();
+        let n = inner.queue.iter().take_while(|value| pred(value)).count();
+        inner.pop_many(&mut discarded, n);
+        drop(inner);
+        drop(discarded);
+        n
+    }
+
+    /// an iterator over the values that are queued right now, without waiting for more
+    pub fn try_iter(&mut self) -> TryIter<'_, T> {
+        TryIter(self)
+    }
+
+    /// block the current thread until a value is available or all senders are gone.
+    ///
+    /// Mixing this with the async `Stream` impl on the same receiver is allowed, but the
+    /// queue only remembers the last waiting party, so a send will wake whichever of the
+    /// blocked thread or the async task registered most recently.
+    #[cfg(feature = "std")]
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        let waker = task::waker(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match Pin::new(&mut *self).poll_next(&mut cx) {
+                Poll::Ready(Some(value)) => break Ok(value),
+                Poll::Ready(None) => break Err(RecvError::Disconnected),
+                // spurious unparks just cause another round
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// like `recv`, but gives up after `timeout`.
+    #[cfg(feature = "std")]
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let waker = task::waker(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match Pin::new(&mut *self).poll_next(&mut cx) {
+                Poll::Ready(Some(value)) => break Ok(value),
+                Poll::Ready(None) => break Err(RecvTimeoutError::Disconnected),
+                Poll::Pending => {
+                    // recompute the remaining time, so spurious unparks don't extend the wait
+                    let now = Instant::now();
+                    if now >= deadline {
+                        // don't leave the thread waker behind for the next send to hit
+                        self.0.lock().waker = None;
+                        break Err(RecvTimeoutError::Timeout);
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        }
+    }
+
+    /// a future that resolves once the last sender is dropped, even if values are still
+    /// queued. Resolves right away if there are no senders left.
+    ///
+    /// Closing the queue does not count, see `close_reason` to tell the two apart.
+    pub fn all_senders_dropped(&mut self) -> AllSendersDropped<'_, T> {
+        AllSendersDropped {
+            receiver: self,
+            parked: false,
+        }
+    }
+
+    /// receive the next value, or `None` once all senders are gone.
+    ///
+    /// This is the same as `StreamExt::next`, but returns a named future. The future is
+    /// cancel safe: if it is dropped before completion, no value is lost.
+    pub fn recv_async(&mut self) -> Recv<'_, T> {
+        Recv {
+            receiver: self,
+            parked: false,
+        }
+    }
+
+    /// like `recv_async`, but with a result that says explicitly when the queue is closed.
+    ///
+    /// Cancel safe like `recv_async`.
+    pub fn recv_or_closed(&mut self) -> RecvOrClosed<'_, T> {
+        RecvOrClosed(self.recv_async())
+    }
+
+    /// a stream of batches of `n` values, taken from the queue under a single lock.
+    ///
+    /// Waits until `n` values are available. Once all senders are gone, the remaining
+    /// values are yielded as a final, shorter batch. Panics if `n` is 0.
+    pub fn into_chunks(self, n: usize) -> Chunks<T> {
+        assert!(n > 0, "chunk size must be at least 1");
+        Chunks {
+            receiver: self,
+            buf: Vec::with_capacity(n),
+            n,
+        }
+    }
+
+    /// a stream of `f` applied to every value, as a type that can be named
+    pub fn map<U, F: FnMut(T) -> U>(self, f: F) -> Map<T, F> {
+        Map { receiver: self, f }
+    }
+
+    /// a stream of the values for which `pred` returns true, as a type that can be named
+    pub fn filter<F: FnMut(&T) -> bool>(self, pred: F) -> Filter<T, F> {
+        Filter {
+            receiver: self,
+            pred,
+        }
+    }
+
+    /// the receiver as a boxed trait object, e.g. to store receivers of different queue
+    /// kinds together.
+    ///
+    /// Unlike `StreamExt::boxed`, the result is still a `FusedStream`.
+    pub fn boxed(self) -> BoxFusedStream<T>
+    where
+        T: Send + 'static,
+    {
+        Box::pin(self)
+    }
+
+    /// a stream that yields every value together with the queue len after taking it.
+    ///
+    /// The len is read under the same lock as the value, so unlike calling `len`
+    /// separately it can't be off because of concurrent sends.
+    pub fn with_depth(self) -> WithDepth<T> {
+        WithDepth(self)
+    }
+
+    /// a stream that yields only the latest value of a burst.
+    ///
+    /// Once a value arrives, this waits for `window`, and then yields the latest value
+    /// that arrived in the meantime, dropping the ones before it. When all senders are
+    /// gone, a pending value is yielded right away.
+    #[cfg(feature = "timer")]
+    pub fn debounce(self, window: Duration) -> Debounce<T> {
+        Debounce {
+            receiver: self,
+            window,
+            latest: None,
+            delay: None,
+        }
+    }
+
+    /// a stream that yields `Err(Idle)` whenever nothing arrived for `timeout`.
+    ///
+    /// The timeout starts when this is called, and restarts after every value and every
+    /// `Idle`, so a stalled producer produces an `Idle` every `timeout`. The stream ends
+    /// like the receiver, once all senders are gone.
+    #[cfg(feature = "timer")]
+    pub fn with_idle_timeout(self, timeout: Duration) -> IdleTimeout<T> {
+        IdleTimeout {
+            receiver: self,
+            timeout,
+            delay: futures_timer::Delay::new(timeout),
+        }
+    }
+
+    /// move up to `max` queued values into `buf` under a single lock, without waiting.
+    ///
+    /// Returns the number of values moved. A return value of 0 means that nothing was
+    /// queued, use `is_connected` to find out if more values can arrive.
+    ///
+    /// `buf` can be any collection, e.g. a `SmallVec` to receive small batches without
+    /// allocating.
+    pub fn recv_many(&mut self, buf: &mut impl Extend<T>, max: usize) -> usize {
+        self.0.lock().pop_many(buf, max)
+    }
+
+    /// take up to `N` queued values under a single lock, without waiting and without
+    /// allocating.
+    ///
+    /// An empty batch means that nothing was queued, like a return value of 0 from
+    /// `recv_many`.
+    #[cfg(feature = "smallvec")]
+    pub fn recv_many_smallvec<const N: usize>(&mut self) -> smallvec::SmallVec<[T; N]> {
+        let mut batch = smallvec::SmallVec::new();
+        self.recv_many(&mut batch, N);
+        batch
+    }
+
+    /// like `recv_many`, but waits until at least one value is available.
+    ///
+    /// Resolves to 0 once all senders are gone and the queue is drained, or immediately
+    /// when `max` is 0.
+    pub fn poll_recv_many(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut impl Extend<T>,
+        max: usize,
+    ) -> Poll<usize> {
+        if max == 0 {
+            return Poll::Ready(0);
+        }
+        let mut inner = self.0.lock();
+        if inner.park_if_paused(cx.waker()) {
+            return Poll::Pending;
+        }
+        let n = inner.pop_many(buf, max);
+        if n > 0 || inner.senders_done() {
+            Poll::Ready(n)
+        } else {
+            inner.park_receiver(cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+/// helpers for queues of byte chunks
+#[cfg(feature = "bytes")]
+impl Receiver<bytes::Bytes> {
+    /// take all queued chunks under a single lock, and join them into one.
+    ///
+    /// A single chunk is returned as it is, without copying. Returns `None` without
+    /// waiting if nothing is queued.
+    pub fn recv_concat(&mut self) -> Option<bytes::Bytes> {
+        let mut chunks = Vec::new();
+        let n = self.recv_many(&mut chunks, usize::MAX);
+        if n <= 1 {
+            return chunks.pop();
+        }
+        let mut bytes = bytes::BytesMut::with_capacity(chunks.iter().map(|c| c.len()).sum());
+        for chunk in &chunks {
+            bytes.extend_from_slice(chunk);
+        }
+        Some(bytes.freeze())
+    }
+}
+
+impl<T> Consumer<T> for Receiver<T> {
+    fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        Receiver::try_recv(self)
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+    fn poll_next(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut inner = self.0.lock();
+        if inner.park_if_paused(ctx.waker()) {
+            return Poll::Pending;
+        }
+        if let Some(value) = inner.pop() {
+            Poll::Ready(Some(value))
+        } else if inner.senders_done() {
+            Poll::Ready(None)
+        } else {
+            inner.park_receiver(ctx.waker());
+            Poll::Pending
+        }
+    }
+
+    /// the queued values, and an upper bound once no more values can arrive
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let inner = self.0.lock();
+        let len = inner.queue.len();
+        (
+            len,
+            if inner.senders_done() {
+                Some(len)
+            } else {
+                None
+            },
+        )
+    }
+}
+
+impl<T> FusedStream for Receiver<T> {
+    fn is_terminated(&self) -> bool {
+        let inner = self.0.lock();
+        inner.queue.is_empty() && inner.senders_done()
+    }
+}
+
+/// a `futures::sink::Sink` over a `Sender`.
+///
+/// Flushing waits until the receiver has taken everything out of the queue, so it tells
+/// a producer that the values it has sent have made progress. If the receiver is slow
+/// or not polling at all, a flush can take a long time. It only waits as long as the
+/// receiver is alive, though. Closing the sink flushes first, and then drops the sender.
+pub struct Sink<T> {
+    sender: Option<Sender<T>>,
+    /// key in `sender_wakers` while waiting for room
+    ready_key: Option<usize>,
+    /// key in `drain_wakers` while waiting for a flush
+    flush_key: Option<usize>,
+    /// slots claimed by `poll_ready`, which count towards `QueueInner::reserved` until
+    /// `start_send` uses them
+    slots: usize,
+}
+
+/// a clone has its own sender, so it stays open when the original is closed. Cloning a
+/// closed sink gives a closed sink
+impl<T> Clone for Sink<T> {
+    fn clone(&self) -> Self {
+        Sink {
+            sender: self.sender.clone(),
+            ready_key: None,
+            flush_key: None,
+            slots: 0,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sink<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sink")
+            .field("sender", &self.sender)
+            .field("waiting_for_room", &self.ready_key.is_some())
+            .field("waiting_for_flush", &self.flush_key.is_some())
+            .field("slots", &self.slots)
+            .finish()
+    }
+}
+
+impl<T> Sink<T> {
+    /// true if the sink has been closed using `poll_close`
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_none()
+    }
+
+    /// a sink that collects values locally, and only sends them to the queue under a
+    /// single lock when `capacity` values have been collected, or on flush or close.
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn buffered(self, capacity: usize) -> BufferedSink<T> {
+        assert!(capacity > 0, "buffer capacity must be at least 1");
+        BufferedSink {
+            sink: self,
+            buf: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// like `poll_ready`, but only ready once there is room for at least `n` values, so
+    /// a batch can be sent without parking after every value.
+    ///
+    /// The room is claimed for this sink until it is used by `start_send`, or given back
+    /// when the sink is closed or dropped, so other senders can't fill the queue in
+    /// between.
+    ///
+    /// Like `poll_ready`, this does not wait if the overflow policy is not `Block`.
+    /// Fails with `SinkError::Full` if `n` is larger than the capacity of the queue,
+    /// since there would never be room for that many values.
+    pub fn poll_ready_n(&mut self, cx: &mut Context<'_>, n: usize) -> Poll<Result<(), SinkError>> {
+        if let Some(sender) = &self.sender {
+            let mut inner = sender.0.lock();
+            if inner.receiver_dropped {
+                return Poll::Ready(Err(SinkError::ReceiverDropped));
+            } else if inner.closed {
+                return Poll::Ready(Err(SinkError::Closed));
+            } else if inner.capacity.is_some_and(|capacity| n > capacity.max(1)) {
+                return Poll::Ready(Err(SinkError::Full));
+            }
+            let missing = n.saturating_sub(self.slots);
+            if missing > 0 && inner.free_slots() >= missing {
+                // claim the room, so other senders can't take it before start_send
+                inner.reserved += missing;
+                self.slots += missing;
+            } else if missing > 0 && inner.policy == OverflowPolicy::Block {
+                inner
+                    .sender_wakers
+                    .register(&mut self.ready_key, cx.waker());
+                return Poll::Pending;
+            }
+            // without room, start_send applies the overflow policy
+            inner.sender_wakers.remove(self.ready_key.take());
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Ready(Err(SinkError::Closed))
+        }
+    }
+
+    /// get back the sender, to continue with `Sender::send`.
+    ///
+    /// Returns `None` if the sink has already been closed.
+    pub fn into_sender(mut self) -> Option<Sender<T>> {
+        self.unregister();
+        self.sender.take()
+    }
+
+    /// remove our wakers from the queue, since we are no longer waiting
+    fn unregister(&mut self) {
+        if let Some(sender) = &self.sender {
+            // always take the lock, since we might have been woken for room we didn't use
+            let mut inner = sender.0.lock();
+            if self.slots > 0 {
+                // give back the room we claimed but didn't use
+                inner.reserved -= self.slots;
+                inner.sender_wakers.wake(self.slots);
+                self.slots = 0;
+            }
+            inner.unpark_sender(self.ready_key.take());
+            inner.drain_wakers.remove(self.flush_key.take());
+        }
+    }
+}
+
+impl<T> futures::sink::Sink<T> for Sink<T> {
+    type Error = SinkError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_ready_n(cx, 1)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = &mut *self;
+        if let Some(sender) = &this.sender {
+            let mut inner = sender.0.lock();
+            if inner.receiver_dropped {
+                Err(SinkError::ReceiverDropped)
+            } else if inner.closed {
+                Err(SinkError::Closed)
+            } else if this.slots > 0 {
+                // use the room claimed by poll_ready
+                this.slots -= 1;
+                inner.reserved -= 1;
+                inner.push(item);
+                Ok(())
+            } else {
+                inner.offer(item).map(|_| ()).map_err(|_| SinkError::Full)
+            }
+        } else {
+            Err(SinkError::Closed)
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = &mut *self;
+        if let Some(sender) = &this.sender {
+            let mut inner = sender.0.lock();
+            if inner.receiver_dropped {
+                inner.drain_wakers.remove(this.flush_key.take());
+                Poll::Ready(Err(SinkError::ReceiverDropped))
+            } else if inner.queue.is_empty() {
+                inner.drain_wakers.remove(this.flush_key.take());
+                Poll::Ready(Ok(()))
+            } else {
+                inner.drain_wakers.register(&mut this.flush_key, cx.waker());
+                Poll::Pending
+            }
+        } else {
+            Poll::Ready(Err(SinkError::Closed))
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // closing flushes first, like for any sink. A sink that is closed already is done
+        let result = if self.sender.is_some() {
+            match self.as_mut().poll_flush(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            }
+        } else {
+            Ok(())
+        };
+        self.unregister();
+        self.sender = None;
+        Poll::Ready(result)
+    }
+}
+
+/// a sink that sends values to the queue in batches, see `Sink::buffered`.
+///
+/// Values only go to the queue once the buffer is full or the sink is flushed. So
+/// feeding a value just buffers it, while `SinkExt::send` flushes it to the queue.
+///
+/// Closing moves the buffered values to the queue first, waiting for room if a bounded
+/// queue is full, and then flushes like `Sink`. It fails with `SinkError::ReceiverDropped`
+/// if the receiver goes away in the meantime. A close that is cancelled keeps the values
+/// that are not sent yet in the buffer, so it can be retried.
+///
+/// Dropping the sink sends the buffered values that fit into the queue without waiting.
+/// Values that don't fit into a bounded queue are lost, so close or flush the sink
+/// before dropping it.
+pub struct BufferedSink<T> {
+    sink: Sink<T>,
+    /// values that have not been sent to the queue yet
+    buf: VecDeque<T>,
+    capacity: usize,
+}
+
+// the buffered values are never pinned
+impl<T> Unpin for BufferedSink<T> {}
+
+impl<T> BufferedSink<T> {
+    /// number of values that have not been sent to the queue yet
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// move as many buffered values as fit into the queue
+    fn send_buffered(inner: &mut QueueInner<T>, buf: &mut VecDeque<T>) {
+        while let Some(value) = buf.pop_front() {
+            if let Err(value) = inner.offer(value) {
+                buf.push_front(value);
+                break;
+            }
+        }
+    }
+
+    /// send all buffered values, waiting for room if necessary
+    fn poll_send_buffered(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SinkError>> {
+        if self.buf.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        let sender = match &self.sink.sender {
+            Some(sender) => sender,
+            None => return Poll::Ready(Err(SinkError::Closed)),
+        };
+        let mut inner = sender.0.lock();
+        if inner.receiver_dropped {
+            return Poll::Ready(Err(SinkError::ReceiverDropped));
+        } else if inner.closed {
+            return Poll::Ready(Err(SinkError::Closed));
+        }
+        Self::send_buffered(&mut inner, &mut self.buf);
+        if self.buf.is_empty() {
+            inner.sender_wakers.remove(self.sink.ready_key.take());
+            Poll::Ready(Ok(()))
+        } else if inner.policy == OverflowPolicy::Block {
+            inner
+                .sender_wakers
+                .register(&mut self.sink.ready_key, cx.waker());
+            Poll::Pending
+        } else {
+            Poll::Ready(Err(SinkError::Full))
+        }
+    }
+}
+
+impl<T> futures::sink::Sink<T> for BufferedSink<T> {
+    type Error = SinkError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.buf.len() < self.capacity {
+            Poll::Ready(Ok(()))
+        } else {
+            self.poll_send_buffered(cx)
+        }
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        if self.sink.is_closed() {
+            return Err(SinkError::Closed);
+        }
+        self.buf.push_back(item);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.poll_send_buffered(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut self.sink).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.poll_send_buffered(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut self.sink).poll_close(cx),
+            other => other,
+        }
+    }
+}
+
+impl<T> Drop for BufferedSink<T> {
+    fn drop(&mut self) {
+        if let Some(sender) = &self.sink.sender {
+            if !self.buf.is_empty() {
+                let mut inner = sender.0.lock();
+                if !inner.receiver_dropped && !inner.closed {
+                    Self::send_buffered(&mut inner, &mut self.buf);
+                }
+            }
+        }
+        // whatever is left is dropped after the lock is released
+    }
+}
+
+impl<T> Drop for Sink<T> {
+    fn drop(&mut self) {
+        // don't leave a waker behind if we got dropped while waiting
+        self.unregister();
+    }
+}
+
+/// both ends of a queue in one handle, which is a `futures::sink::Sink` and a `Stream`.
+///
+/// Flushing waits for the queue to be drained, so don't flush a channel from the same
+/// task that reads from it.
+pub struct Channel<T> {
+    sink: Sink<T>,
+    receiver: Receiver<T>,
+}
+
+impl<T> Channel<T> {
+    /// an unbounded channel
+    pub fn new() -> Self {
+        mpsc().into()
+    }
+
+    /// split the channel into its ends. Once the sink is closed or dropped, the receiver
+    /// still yields everything that is queued
+    pub fn split(self) -> (Sink<T>, Receiver<T>) {
+        (self.sink, self.receiver)
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// combine the ends of any kind of queue, e.g. from `bounded`
+impl<T> From<(Sender<T>, Receiver<T>)> for Channel<T> {
+    fn from((sender, receiver): (Sender<T>, Receiver<T>)) -> Self {
+        Channel {
+            sink: sender.sink(),
+            receiver,
+        }
+    }
+}
+
+impl<T> futures::sink::Sink<T> for Channel<T> {
+    type Error = SinkError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        Pin::new(&mut self.sink).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_close(cx)
+    }
+}
+
+impl<T> Stream for Channel<T> {
+    type Item = T;
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(ctx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.receiver.size_hint()
+    }
+}
+
+impl<T> FusedStream for Channel<T> {
+    fn is_terminated(&self) -> bool {
+        self.receiver.is_terminated()
+    }
+}
+
+/// send all values of `source` to the queue, waiting for room on a bounded queue.
+///
+/// A dropped receiver is a normal way to stop, so this resolves to `Ok(())` once the
+/// receiver is gone, without taking more values from `source`. The value that could not
+/// be sent is dropped then. Any other failure to send hands back the value.
+pub async fn pump<T, S: Stream<Item = T>>(
+    source: S,
+    sender: Sender<T>,
+) -> Result<(), SendError<T>> {
+    futures::pin_mut!(source);
+    while !sender.is_cancelled() {
+        match source.next().await {
+            Some(value) => match sender.send_async(value).await {
+                Ok(_) | Err(SendError::ReceiverDropped(_)) => {}
+                Err(cause) => return Err(cause),
+            },
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+pub fn mpsc<T>() -> (Sender<T>, Receiver<T>) {
+    channel(QueueInner::new(None, OverflowPolicy::Block))
+}
+
+/// an unbounded queue that calls `on_event` for everything that happens to it, e.g. to
+/// feed a tracing or metrics library.
+///
+/// `on_event` is called while the queue is locked, so it must be quick and must not
+/// use the queue. Queues without an observer don't pay for this.
+pub fn mpsc_observed<T>(
+    on_event: impl Fn(ChannelEvent) + Send + Sync + 'static,
+) -> (Sender<T>, Receiver<T>) {
+    let mut inner = QueueInner::new(None, OverflowPolicy::Block);
+    inner.observer = Some(Arc::new(on_event));
+    channel(inner)
+}
+
+/// an unbounded queue where every value comes with the time it was queued, e.g. to
+/// measure how long values wait in the queue.
+///
+/// The time is taken while the queue is locked, so the stamps are in queue order.
+#[cfg(feature = "std")]
+pub fn mpsc_timed<T>() -> (TimedSender<T>, Receiver<(Instant, T)>) {
+    let (sender, receiver) = mpsc();
+    (TimedSender(sender), receiver)
+}
+
+/// an unbounded queue that reports a `BackpressureLevel` in
+/// `Sender::send_with_backpressure_hint` once its len reaches `elevated` or `critical`.
+///
+/// Panics if `elevated` is larger than `critical`.
+pub fn mpsc_with_thresholds<T>(elevated: usize, critical: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(elevated <= critical, "elevated threshold is above critical");
+    let mut inner = QueueInner::new(None, OverflowPolicy::Block);
+    inner.thresholds = Some((elevated, critical));
+    channel(inner)
+}
+
+/// a queue where fullness is the total weight of the queued values, as computed by
+/// `weigh`, rather than their number, e.g. to limit the number of bytes in flight.
+///
+/// `Sender::send` fails with `SendError::Full` if the value would take the total weight
+/// above `max_weight`, and `Sender::send_async` waits until it doesn't. A value that is
+/// heavier than `max_weight` is accepted once the queue is empty, since it could never be
+/// sent otherwise. The `Sink`, `Sender::reserve` and `MappedSender` claim room before
+/// they know the weight of the value, so they only check that the total weight is below
+/// `max_weight`, and can go over it by one value.
+///
+/// Panics if `max_weight` is 0.
+pub fn weighted_bounded<T>(
+    max_weight: usize,
+    weigh: impl Fn(&T) -> usize + Send + 'static,
+) -> (Sender<T>, Receiver<T>) {
+    assert!(max_weight > 0, "max_weight must be positive");
+    let mut inner = QueueInner::new(None, OverflowPolicy::Block);
+    inner.weight = Some(Weight {
+        max: max_weight,
+        current: 0,
+        weigh: Box::new(weigh),
+    });
+    channel(inner)
+}
+
+/// an unbounded queue with room for `capacity` items before it has to allocate.
+///
+/// This is only a hint to avoid reallocations, unlike with `bounded` the queue can grow
+/// beyond `capacity`.
+pub fn mpsc_with_capacity<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let mut inner = QueueInner::new(None, OverflowPolicy::Block);
+    inner.queue = VecDeque::with_capacity(capacity);
+    channel(inner)
+}
+
+/// a queue that holds at most `capacity` items.
+///
+/// `Sender::send` fails with `SendError::Full` when the queue is at capacity, and the
+/// `Sink` will wait until the receiver makes room. A capacity of 0 gives a rendezvous
+/// channel, where an item is only accepted when the receiver is waiting for it.
+///
+/// Waiting senders are served in the order they started waiting: every value the
+/// receiver takes wakes the sender that has been waiting the longest, and a woken sender
+/// that finds the room taken by someone else keeps its place at the front. Senders that
+/// don't wait, like `Sender::send`, can still take room before a woken sender gets to it.
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    bounded_with_policy(capacity, OverflowPolicy::Block)
+}
+
+/// a queue that holds at most `capacity` items, with a policy for what to do when full.
+///
+/// With `DropOldest` this is a ring buffer that only keeps the most recent values.
+pub fn bounded_with_policy<T>(capacity: usize, policy: OverflowPolicy) -> (Sender<T>, Receiver<T>) {
+    channel(QueueInner::new(Some(capacity), policy))
+}
+
+/// a queue with any combination of the options of the other constructors.
+///
+/// `ChannelBuilder::<u32>::new().capacity(16).overflow(OverflowPolicy::DropOldest).build()`
+/// is the same as `bounded_with_policy(16, OverflowPolicy::DropOldest)`.
+pub struct ChannelBuilder<T> {
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    initial_capacity: usize,
+    thresholds: Option<(usize, usize)>,
+    metrics: bool,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> ChannelBuilder<T> {
+    /// an unbounded queue, like `mpsc`
+    pub fn new() -> Self {
+        ChannelBuilder {
+            capacity: None,
+            policy: OverflowPolicy::Block,
+            initial_capacity: 0,
+            thresholds: None,
+            metrics: true,
+            _value: PhantomData,
+        }
+    }
+
+    /// hold at most `capacity` values, like `bounded`
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// what to do when a bounded queue is full, like `bounded_with_policy`
+    pub fn overflow(mut self, policy: OverflowPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// allocate room for `initial_capacity` values up front, like `mpsc_with_capacity`
+    pub fn initial_capacity(mut self, initial_capacity: usize) -> Self {
+        self.initial_capacity = initial_capacity;
+        self
+    }
+
+    /// backpressure thresholds, like `mpsc_with_thresholds`.
+    ///
+    /// Panics if `elevated` is larger than `critical`.
+    pub fn thresholds(mut self, elevated: usize, critical: usize) -> Self {
+        assert!(elevated <= critical, "elevated threshold is above critical");
+        self.thresholds = Some((elevated, critical));
+        self
+    }
+
+    /// collect `QueueMetrics`, which is the default like for the other constructors.
+    ///
+    /// `metrics(false)` saves updating the counters on every send and receive. Without
+    /// the `metrics` feature nothing is collected, and this does nothing.
+    pub fn metrics(mut self, enabled: bool) -> Self {
+        self.metrics = enabled;
+        self
+    }
+
+    /// create the queue
+    pub fn build(self) -> (Sender<T>, Receiver<T>) {
+        let mut inner = QueueInner::new(self.capacity, self.policy);
+        inner.queue = VecDeque::with_capacity(self.initial_capacity);
+        inner.thresholds = self.thresholds;
+        #[cfg(feature = "metrics")]
+        if !self.metrics {
+            inner.metrics = None;
+        }
+        channel(inner)
+    }
+}
+
+impl<T> Default for ChannelBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// an unbounded queue that starts out with the values from a snapshot
+pub fn mpsc_from_snapshot<T>(snapshot: QueueSnapshot<T>) -> (Sender<T>, Receiver<T>) {
+    from_iter(snapshot.0)
+}
+
+/// an unbounded queue that starts out with the given values
+pub fn from_iter<T, I: IntoIterator<Item = T>>(items: I) -> (Sender<T>, Receiver<T>) {
+    let mut inner = QueueInner::new(None, OverflowPolicy::Block);
+    inner.queue = items.into_iter().collect();
+    inner.next_seq = inner.queue.len() as u64;
+    #[cfg(feature = "metrics")]
+    if let Some(metrics) = &mut inner.metrics {
+        metrics.high_water_mark = inner.queue.len();
+    }
+    channel(inner)
+}
+
+fn channel<T>(inner: QueueInner<T>) -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Shared::new(inner));
+    (Sender(inner.clone()), Receiver(inner))
+}
+
+// values are never pinned, so the handles are Unpin no matter what T is
+const _: fn() = || {
+    fn assert_unpin<T: Unpin>() {}
+    type NotUnpin = core::marker::PhantomPinned;
+    assert_unpin::<Sender<NotUnpin>>();
+    assert_unpin::<WeakSender<NotUnpin>>();
+    assert_unpin::<Receiver<NotUnpin>>();
+    assert_unpin::<Sink<NotUnpin>>();
+    assert_unpin::<BufferedSink<NotUnpin>>();
+    assert_unpin::<Channel<NotUnpin>>();
+    assert_unpin::<OwnedPermit<NotUnpin>>();
+};
+
+// the queue state is behind a mutex, so the handles are Send and Sync as long as T is
+// Send. T does not have to be Sync, since values are only ever accessed by one thread.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    type NotSync = core::cell::Cell<u8>;
+    assert_send_sync::<Sender<NotSync>>();
+    assert_send_sync::<WeakSender<NotSync>>();
+    assert_send_sync::<Receiver<NotSync>>();
+    assert_send_sync::<Sink<NotSync>>();
+    // holds values outside of the mutex, so it is only Sync if T is
+    fn assert_send<T: Send>() {}
+    assert_send::<BufferedSink<NotSync>>();
+    assert_send_sync::<Channel<NotSync>>();
+    assert_send_sync::<OwnedPermit<NotSync>>();
+    assert_send_sync::<mpmc::Sender<NotSync>>();
+    assert_send_sync::<mpmc::Receiver<NotSync>>();
+    assert_send_sync::<oneshot::Sender<NotSync>>();
+    assert_send_sync::<oneshot::Receiver<NotSync>>();
+    assert_send_sync::<priority::Sender<NotSync>>();
+    assert_send_sync::<priority::Receiver<NotSync>>();
+    assert_send_sync::<watch::Sender<NotSync>>();
+    assert_send_sync::<watch::Receiver<NotSync>>();
+    assert_send_sync::<broadcast::Sender<NotSync>>();
+    assert_send_sync::<broadcast::Receiver<NotSync>>();
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{
+        executor::block_on,
+        future,
+        sink::{Sink as _, SinkExt},
+        stream::StreamExt,
+        task::{self, ArcWake},
+    };
+    use std::{
+        string::{String, ToString},
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn smoke() {}
+
+    #[test]
+    fn mpsc_with_capacity() {
+        let (sender, receiver) = super::mpsc_with_capacity::<u32>(16);
+        assert!(receiver.0.lock().queue.capacity() >= 16);
+        assert_eq!(receiver.capacity(), None);
+        sender.send_all(0..32).unwrap();
+        assert_eq!(receiver.len(), 32);
+    }
+
+    #[test]
+    fn producer_consumer() {
+        fn roundtrip(producer: &dyn Producer<u32>, consumer: &mut dyn Consumer<u32>) {
+            producer.try_send(1).unwrap();
+            assert_eq!(consumer.try_recv(), Ok(1));
+            assert_eq!(consumer.try_recv(), Err(TryRecvError::Empty));
+        }
+        let (sender, mut receiver) = bounded::<u32>(2);
+        assert_eq!(Producer::capacity(&sender), Some(2));
+        roundtrip(&sender, &mut receiver);
+        let (sender, mut receiver) = mpmc::<u32>();
+        roundtrip(&sender, &mut receiver);
+        let (sender, mut receiver) = priority_queue::<u32>();
+        roundtrip(&sender, &mut receiver);
+        drop(sender);
+        assert_eq!(
+            Consumer::try_recv(&mut receiver),
+            Err(TryRecvError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn pump() {
+        let (sender, receiver) = bounded::<u32>(1);
+        let (received, result) = block_on(future::join(
+            receiver.collect::<Vec<_>>(),
+            super::pump(futures::stream::iter(0..3), sender),
+        ));
+        assert_eq!(received, vec![0, 1, 2]);
+        assert_eq!(result, Ok(()));
+        let (sender, receiver) = mpsc::<u32>();
+        drop(receiver);
+        assert_eq!(
+            block_on(super::pump(futures::stream::repeat(0), sender)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn send_timeout() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        assert_eq!(sender.send_timeout(0, Duration::from_millis(10)), Ok(1));
+        assert_eq!(
+            sender.send_timeout(1, Duration::from_millis(10)),
+            Err(SendTimeoutError::Timeout(1))
+        );
+        // the timed out sender does not hold on to the room
+        assert_eq!(receiver.try_recv(), Ok(0));
+        assert_eq!(sender.send(2), Ok(1));
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            drop(receiver);
+        });
+        assert_eq!(
+            sender.send_timeout(3, Duration::from_secs(10)),
+            Err(SendTimeoutError::Disconnected(3))
+        );
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn size_hint() {
+        let (sender, receiver) = mpsc::<u32>();
+        sender.send_all(0..2).unwrap();
+        assert_eq!(receiver.size_hint(), (2, None));
+        drop(sender);
+        assert_eq!(receiver.size_hint(), (2, Some(2)));
+    }
+
+    #[test]
+    fn channel_builder() {
+        let (sender, mut receiver) = ChannelBuilder::<u32>::new()
+            .capacity(2)
+            .overflow(OverflowPolicy::DropOldest)
+            .initial_capacity(2)
+            .thresholds(1, 2)
+            .metrics(true)
+            .build();
+        assert_eq!(sender.capacity(), Some(2));
+        sender.send_all(0..3).unwrap();
+        assert_eq!(
+            sender.send_with_backpressure_hint(3),
+            Ok(BackpressureLevel::Critical)
+        );
+        assert_eq!(receiver.drain_to_vec(), vec![2, 3]);
+        #[cfg(feature = "metrics")]
+        {
+            assert_eq!(receiver.metrics().total_sent, 4);
+            let (sender, _receiver) = ChannelBuilder::<u32>::new().metrics(false).build();
+            sender.send(0).unwrap();
+            assert_eq!(sender.metrics(), QueueMetrics::default());
+        }
+    }
+
+    #[test]
+    fn send_batch_atomic() {
+        let (sender, mut receiver) = bounded::<u32>(3);
+        sender.send(0).unwrap();
+        assert!(matches!(
+            sender.send_batch_atomic(1..4),
+            Err(SendError::Full(rest)) if rest == vec![1, 2, 3]
+        ));
+        assert_eq!(sender.send_batch_atomic(1..3), Ok(3));
+        assert_eq!(receiver.drain_to_vec(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn error_conversions() {
+        let (sender, receiver) = bounded_with_policy::<u32>(1, OverflowPolicy::Error);
+        sender.send(1).unwrap();
+        let mut sink = sender.clone().sink();
+        let error = SinkError::from(sender.send(2).unwrap_err());
+        assert!(matches!(error, SinkError::Full));
+        let error = Pin::new(&mut sink).start_send(2).unwrap_err();
+        assert_eq!(SendError::from(error), SendError::Full(()));
+        drop(receiver);
+        let error = Pin::new(&mut sink).start_send(2).unwrap_err();
+        assert_eq!(SendError::from(error), SendError::ReceiverDropped(()));
+        assert!(matches!(
+            SinkError::from(SendError::Closed(1)),
+            SinkError::Closed
+        ));
+    }
+
+    #[test]
+    fn send_after_receiver_dropped() {
+        // the receiver is marked as dropped under the lock, so this does not depend on
+        // the receiver ever having been polled
+        let queues = vec![
+            mpsc::<u32>(),
+            bounded(1),
+            super::from_iter(vec![0]),
+            ChannelBuilder::new().capacity(0).build(),
+        ];
+        for (sender, receiver) in queues {
+            drop(receiver);
+            assert!(sender.is_cancelled());
+            assert_eq!(sender.send(1), Err(SendError::ReceiverDropped(1)));
+            assert_eq!(sender.try_send(2), Err(TrySendError::Disconnected(2)));
+            assert_eq!(
+                block_on(sender.send_async(3)),
+                Err(SendError::ReceiverDropped(3))
+            );
+        }
+    }
+
+    #[test]
+    fn send_ref() {
+        let (sender, mut receiver) = bounded::<String>(1);
+        let value = "a".to_string();
+        assert_eq!(sender.send_ref(&value), Ok(1));
+        assert_eq!(sender.send_ref(&value), Err(SendError::Full(())));
+        assert_eq!(receiver.try_recv(), Ok(value.clone()));
+        drop(receiver);
+        assert_eq!(sender.send_ref(&value), Err(SendError::ReceiverDropped(())));
+        // a value that is too heavy is weighed without being cloned
+        let (sender, _receiver) = super::weighted_bounded::<Arc<Vec<u8>>>(4, |v| v.len());
+        sender.send(Arc::new(vec![0; 3])).unwrap();
+        let value = Arc::new(vec![0; 2]);
+        assert_eq!(sender.send_ref(&value), Err(SendError::Full(())));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn send_seq() {
+        let (sender, _receiver) = bounded_with_policy::<u32>(1, OverflowPolicy::DropNewest);
+        let other = sender.clone();
+        assert_eq!(sender.send_seq(0), Ok(0));
+        // a dropped value leaves a gap
+        assert_eq!(other.send(1), Ok(1));
+        assert_eq!(other.send_seq(2), Ok(2));
+        let (sender, _receiver) = super::from_iter(0..3u32);
+        assert_eq!(sender.send_seq(3), Ok(3));
+    }
+
+    #[test]
+    fn backpressure_hint() {
+        let (sender, _receiver) = mpsc_with_thresholds::<u32>(2, 3);
+        let levels = (0..3)
+            .map(|i| sender.send_with_backpressure_hint(i).unwrap())
+            .collect::<Vec<_>>();
+        use BackpressureLevel::*;
+        assert_eq!(levels, vec![Ok, Elevated, Critical]);
+        let (sender, _receiver) = mpsc::<u32>();
+        assert_eq!(sender.send_with_backpressure_hint(0).unwrap(), Ok);
+    }
+
+    #[test]
+    fn weighted_bounded() {
+        let (sender, mut receiver) = super::weighted_bounded::<Vec<u8>>(4, Vec::len);
+        assert_eq!(sender.send(vec![0; 3]), Ok(1));
+        assert_eq!(sender.send(vec![0; 2]), Err(SendError::Full(vec![0; 2])));
+        assert_eq!(sender.send(vec![0]), Ok(2));
+        assert_eq!(sender.send(vec![]), Err(SendError::Full(vec![])));
+        let flag = Arc::<Flag>::default();
+        let mut send = sender.send_async(vec![0; 2]);
+        assert!(flag.poll(&mut send).is_pending());
+        assert_eq!(receiver.try_recv(), Ok(vec![0; 3]));
+        assert!(flag.woken());
+        assert_eq!(flag.poll(&mut send), Poll::Ready(Ok(2)));
+        drop(send);
+        assert_eq!(receiver.try_recv(), Ok(vec![0]));
+        assert_eq!(receiver.try_recv(), Ok(vec![0; 2]));
+        // too heavy for the queue, but it would never get through otherwise
+        assert_eq!(sender.send(vec![0; 10]), Ok(1));
+        assert_eq!(sender.send(vec![]), Err(SendError::Full(vec![])));
+        assert_eq!(receiver.try_recv(), Ok(vec![0; 10]));
+        assert_eq!(
+            sender.send_batch_atomic(vec![vec![0; 2], vec![0; 2]]),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn bounded_full() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        assert_eq!(sender.send(1).unwrap(), 1);
+        assert!(matches!(sender.send(2), Err(SendError::Full(2))));
+        assert_eq!(block_on(receiver.next()), Some(1));
+        assert_eq!(sender.send(2).unwrap(), 1);
+    }
+
+    #[test]
+    fn capacity() {
+        let (sender, receiver) = bounded::<u32>(2);
+        assert_eq!(receiver.capacity(), Some(2));
+        sender.send(0).unwrap();
+        assert_eq!(sender.remaining_capacity(), Some(1));
+        let (sender, _receiver) = mpsc::<u32>();
+        assert_eq!(sender.capacity(), None);
+        assert_eq!(sender.remaining_capacity(), None);
+    }
+
+    #[test]
+    fn lock_free_len() {
+        let (sender, receiver) = mpsc::<u32>();
+        sender.send_all(0..2).unwrap();
+        // these would deadlock if they had to take the lock
+        let guard = sender.0.lock();
+        assert_eq!(sender.queue_len(), 2);
+        assert!(!sender.is_cancelled());
+        drop(guard);
+        drop(receiver);
+        assert_eq!(sender.queue_len(), 0);
+        assert!(sender.is_cancelled());
+    }
+
+    #[test]
+    fn try_send() {
+        let (sender, receiver) = bounded::<u32>(1);
+        assert_eq!(sender.try_send(1), Ok(1));
+        assert_eq!(sender.try_send(2), Err(TrySendError::Full(2)));
+        drop(receiver);
+        assert_eq!(sender.try_send(3).unwrap_err().into_inner(), 3);
+    }
+
+    #[test]
+    fn send_if() {
+        let (sender, receiver) = mpsc::<u32>();
+        assert_eq!(sender.send_if(1, |len| len < 1), Ok(Some(1)));
+        assert_eq!(sender.send_if(2, |len| len < 1), Ok(None));
+        assert_eq!(receiver.len(), 1);
+        drop(receiver);
+        assert_eq!(
+            sender.send_if(3, |_| false),
+            Err(SendError::ReceiverDropped(3))
+        );
+    }
+
+    #[test]
+    fn overflow_policy() {
+        let (sender, mut receiver) = bounded_with_policy::<u32>(2, OverflowPolicy::DropOldest);
+        sender.send_all(0..2).unwrap();
+        assert_eq!(
+            sender.send_with_outcome(2),
+            Ok(SendOutcome::DroppedOldest(0))
+        );
+        assert_eq!(block_on(receiver.next()), Some(1));
+        let (sender, mut receiver) = bounded_with_policy::<u32>(1, OverflowPolicy::DropNewest);
+        assert_eq!(sender.send_with_outcome(0), Ok(SendOutcome::Sent(1)));
+        assert_eq!(
+            sender.send_with_outcome(1),
+            Ok(SendOutcome::DroppedNewest(1))
+        );
+        assert_eq!(block_on(receiver.next()), Some(0));
+        let (sender, _receiver) = bounded_with_policy::<u32>(0, OverflowPolicy::Error);
+        assert!(matches!(
+            block_on(sender.send_async(0)),
+            Err(SendError::Full(0))
+        ));
+    }
+
+    #[test]
+    fn rendezvous_sink() {
+        let (sender, receiver) = bounded::<u32>(0);
+        let mut sink = sender.sink();
+        let producer = async move {
+            for i in 0..10 {
+                sink.send(i).await.unwrap();
+            }
+        };
+        let ((), values) = block_on(future::join(producer, receiver.collect::<Vec<_>>()));
+        assert_eq!(values, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn try_recv() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        sender.send(1).unwrap();
+        drop(sender);
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn sender_count() {
+        let (sender, receiver) = mpsc::<u32>();
+        let sink = sender.clone().sink();
+        assert_eq!(sender.sender_count(), 2);
+        drop(sink);
+        assert_eq!(sender.sender_count(), 1);
+        assert!(receiver.is_connected());
+        drop(sender);
+        assert!(!receiver.is_connected());
+    }
+
+    #[test]
+    fn close() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        sender.send(1).unwrap();
+        sender.close();
+        assert_eq!(sender.send(2), Err(SendError::Closed(2)));
+        assert_eq!(sender.send(3).unwrap_err().into_inner(), 3);
+        assert!(!receiver.is_terminated());
+        assert_eq!(block_on(receiver.next()), Some(1));
+        assert!(receiver.is_terminated());
+        assert_eq!(block_on(receiver.next()), None);
+    }
+
+    #[test]
+    fn peek() {
+        let (sender, mut receiver) = mpsc::<String>();
+        assert_eq!(receiver.peek(), None);
+        sender.send("a".into()).unwrap();
+        assert_eq!(receiver.with_front(|s| s.len()), Some(1));
+        assert_eq!(receiver.peek().as_deref(), Some("a"));
+        assert_eq!(receiver.try_recv().as_deref(), Ok("a"));
+    }
+
+    #[test]
+    fn snapshot() {
+        let (sender, receiver) = mpsc::<u32>();
+        sender.send_all(0..3).unwrap();
+        let snapshot = QueueSnapshot::from(receiver.snapshot());
+        assert_eq!(receiver.len(), 3);
+        let (_sender, receiver) = mpsc_from_snapshot(snapshot);
+        assert_eq!(receiver.snapshot(), vec![0, 1, 2]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_serde() {
+        let (sender, receiver) = mpsc::<String>();
+        sender
+            .send_all(vec!["a".to_string(), "b".to_string()])
+            .unwrap();
+        let json = serde_json::to_string(&QueueSnapshot::from(receiver.snapshot())).unwrap();
+        assert_eq!(json, r#"["a","b"]"#);
+        let snapshot: QueueSnapshot<String> = serde_json::from_str(&json).unwrap();
+        let (_sender, mut receiver) = mpsc_from_snapshot(snapshot);
+        assert_eq!(receiver.try_recv().as_deref(), Ok("a"));
+        assert_eq!(receiver.try_recv().as_deref(), Ok("b"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics() {
+        let (sender, mut receiver) = bounded_with_policy::<u32>(2, OverflowPolicy::DropNewest);
+        sender.send_all(0..3).unwrap();
+        assert_eq!(receiver.try_recv(), Ok(0));
+        assert_eq!(
+            receiver.metrics(),
+            QueueMetrics {
+                total_sent: 2,
+                total_received: 1,
+                high_water_mark: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn from_iter() {
+        let (sender, receiver) = super::from_iter(0..2u32);
+        sender.send(2).unwrap();
+        drop(sender);
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn weak_sender() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let weak = sender.downgrade();
+        weak.upgrade().unwrap().send(1).unwrap();
+        drop(sender);
+        assert_eq!(block_on(receiver.next()), Some(1));
+        assert_eq!(block_on(receiver.next()), None);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn ends_when_senders_are_gone() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let sink = sender.clone().sink();
+        // a temporary strong reference does not keep the receiver going
+        let weak = sender.downgrade();
+        drop(sender);
+        drop(sink);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let _upgrading = weak.0.upgrade();
+        assert!(matches!(
+            Pin::new(&mut receiver).poll_next(&mut cx),
+            Poll::Ready(None)
+        ));
+        assert!(receiver.is_terminated());
+    }
+
+    #[test]
+    fn mapped_sender() {
+        let (sender, mut receiver) = mpsc::<String>();
+        let sender = sender.with(|x: u32| x.to_string());
+        assert_eq!(sender.send(1), Ok(1));
+        assert_eq!(receiver.try_recv(), Ok("1".to_string()));
+        let mut sink = sender.clone().sink();
+        Pin::new(&mut sink).start_send(2).unwrap();
+        assert_eq!(receiver.try_recv(), Ok("2".to_string()));
+        drop(receiver);
+        let sender = sender
+            .into_inner()
+            .with(|_: u32| -> String { panic!("not called") });
+        assert_eq!(sender.send(3), Err(SendError::ReceiverDropped(3)));
+    }
+
+    #[test]
+    fn mapped_sender_converts_outside_the_lock() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        let probe = sender.clone();
+        // is_empty takes the lock, so this would deadlock if f was called under it
+        let mapped = sender.clone().with(move |x: u32| {
+            assert!(probe.is_empty());
+            x
+        });
+        assert_eq!(mapped.send(1), Ok(1));
+        assert_eq!(receiver.try_recv(), Ok(1));
+        let panicking = sender
+            .clone()
+            .with(|_: u32| -> u32 { panic!("conversion failed") });
+        let result = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| panicking.send(2)));
+        assert!(result.is_err());
+        // the slot claimed for the value is free again, and the queue still works
+        assert_eq!(sender.send(3), Ok(1));
+        assert_eq!(receiver.try_recv(), Ok(3));
+    }
+
+    #[test]
+    fn debug() {
+        // no Debug impl for the payload
+        struct Opaque;
+        let (sender, receiver) = mpsc::<Opaque>();
+        sender.send(Opaque).ok();
+        assert_eq!(
+            format!("{:?}", sender),
+            "Sender { queue_len: 1, receiver_dropped: false, sender_count: 1, receiver_waiting: false }"
+        );
+        assert_eq!(
+            format!("{:?}", receiver),
+            "Receiver { queue_len: 1, sender_count: 1, closed: false, waiting: false }"
+        );
+        assert!(format!("{:?}", sender.sink()).starts_with("Sink { sender: Some(Sender {"));
+    }
+
+    #[test]
+    fn try_iter() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        sender.send_all(0..3).unwrap();
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+        sender.send(3).unwrap();
+        drop(sender);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn send_priority() {
+        let (sender, receiver) = mpsc::<u32>();
+        sender.send_priority(0, false).unwrap();
+        sender.send_priority(1, true).unwrap();
+        sender.send_priority(2, false).unwrap();
+        sender.send_priority(3, true).unwrap();
+        drop(sender);
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), vec![1, 3, 0, 2]);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_priority() {
+        let (sender, receiver) = bounded_with_policy::<u32>(3, OverflowPolicy::DropOldest);
+        sender.send_priority(0, true).unwrap();
+        sender.send_all(1..3).unwrap();
+        // the oldest normal values make room, not the older high priority one
+        sender.send_priority(3, true).unwrap();
+        sender.send_priority(4, true).unwrap();
+        assert_eq!(receiver.snapshot(), vec![0, 3, 4]);
+        // a normal value never pushes out a high priority one
+        assert_eq!(
+            sender.send_with_outcome(5),
+            Ok(SendOutcome::DroppedNewest(5))
+        );
+        sender.send_priority(6, true).unwrap();
+        drop(sender);
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), vec![3, 4, 6]);
+    }
+
+    #[test]
+    fn send_dedup() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        assert_eq!(sender.send_dedup(1), Ok(SendOutcome::Sent(1)));
+        assert_eq!(sender.send_dedup(1), Ok(SendOutcome::Duplicate(1)));
+        assert_eq!(sender.send_dedup(2), Ok(SendOutcome::Sent(2)));
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+        // only queued values count
+        assert_eq!(sender.send_dedup(2), Ok(SendOutcome::Sent(1)));
+    }
+
+    #[test]
+    fn has_waker() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut receiver).poll_next(&mut cx).is_pending());
+        assert!(sender.has_waker() && receiver.has_waker());
+        // sending wakes the receiver, which takes the waker
+        sender.send(1).unwrap();
+        assert!(!receiver.has_waker());
+    }
+
+    #[test]
+    fn retain_recv() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        sender.send_all(0..6).unwrap();
+        assert_eq!(receiver.retain_recv(|x| *x >= 2), Some(2));
+        assert_eq!(receiver.discard_while(|x| *x < 5), 2);
+        assert_eq!(receiver.retain_recv(|x| *x > 5), None);
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn drain_to_vec() {
+        let (sender, mut receiver) = bounded::<u32>(3);
+        sender.send_all(0..3).unwrap();
+        assert_eq!(receiver.drain_to_vec(), vec![0, 1, 2]);
+        assert!(receiver.drain_to_vec().is_empty());
+        // there is room again
+        assert_eq!(sender.try_send(3), Ok(1));
+    }
+
+    #[test]
+    fn boxed() {
+        let (sender, receiver) = mpsc::<u32>();
+        let mut streams: Vec<BoxFusedStream<u32>> = vec![receiver.boxed()];
+        sender.send(0).unwrap();
+        drop(sender);
+        assert_eq!(block_on(streams[0].next()), Some(0));
+        assert_eq!(block_on(streams[0].next()), None);
+        assert!(streams[0].is_terminated());
+    }
+
+    #[test]
+    fn wait_for() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        sender.send_all(0..3).unwrap();
+        let flag = Arc::<Flag>::default();
+        let mut wait = receiver.wait_for(|x| *x >= 5);
+        assert!(flag.poll(&mut wait).is_pending());
+        sender.send(5).unwrap();
+        assert!(flag.woken());
+        assert_eq!(flag.poll(&mut wait), Poll::Ready(Some(5)));
+        drop(wait);
+        sender.send(6).unwrap();
+        drop(sender);
+        assert_eq!(block_on(receiver.wait_for(|x| *x > 6)), None);
+        // values that don't match are gone
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn try_recv_with() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        assert_eq!(
+            receiver.try_recv_with(|_| (true, ())),
+            Err(TryRecvError::Empty)
+        );
+        sender.send_all(0..2).unwrap();
+        assert_eq!(receiver.try_recv_with(|x| (false, *x)), Ok(0));
+        assert_eq!(receiver.try_recv_with(|x| (true, *x)), Ok(0));
+        assert_eq!(receiver.try_recv_with(|x| (true, *x)), Ok(1));
+        drop(sender);
+        assert_eq!(
+            receiver.try_recv_with(|x| (true, *x)),
+            Err(TryRecvError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn recv_many() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let mut buf = Vec::new();
+        for i in 0..5 {
+            sender.send(i).unwrap();
+        }
+        assert_eq!(receiver.recv_many(&mut buf, 0), 0);
+        assert_eq!(receiver.recv_many(&mut buf, 3), 3);
+        assert_eq!(receiver.recv_many(&mut buf, 3), 2);
+        assert_eq!(buf, vec![0, 1, 2, 3, 4]);
+        // any collection works, not just a Vec
+        let mut deque = VecDeque::new();
+        sender.send_all(5..8).unwrap();
+        assert_eq!(receiver.recv_many(&mut deque, 3), 3);
+        assert_eq!(deque, vec![5, 6, 7]);
+        drop(sender);
+        let n = block_on(future::poll_fn(|cx| {
+            receiver.poll_recv_many(cx, &mut buf, 3)
+        }));
+        assert_eq!(n, 0);
+    }
+
+    #[test]
+    fn send_all() {
+        let (sender, mut receiver) = bounded::<u32>(3);
+        assert_eq!(sender.send_all(vec![1, 2]).unwrap(), 2);
+        assert!(matches!(sender.send_all(3..6), Err(SendError::Full(rest)) if rest == vec![4, 5]));
+        let mut buf = Vec::new();
+        receiver.recv_many(&mut buf, 10);
+        assert_eq!(buf, vec![1, 2, 3]);
+        drop(receiver);
+        assert!(matches!(
+            sender.send_all(0..1),
+            Err(SendError::ReceiverDropped(rest)) if rest == vec![0]
+        ));
+    }
+
+    #[test]
+    fn poisoned_lock() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let poisoner = sender.clone();
+        std::thread::spawn(move || {
+            let _guard = poisoner.0.lock();
+            panic!("poisoning the lock");
+        })
+        .join()
+        .unwrap_err();
+        sender.send(1).unwrap();
+        assert_eq!(receiver.try_recv(), Ok(1));
+    }
+
+    #[test]
+    fn close_and_drain() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let other = sender.clone();
+        sender.send(1).unwrap();
+        let mut drain = sender.close_and_drain();
+        assert_eq!(other.send(2), Err(SendError::Closed(2)));
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut drain).poll(&mut cx).is_pending());
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert!(Pin::new(&mut drain).poll(&mut cx).is_ready());
+        // nothing to drain once the receiver is gone
+        let (sender, receiver) = mpsc::<u32>();
+        sender.send(1).unwrap();
+        drop(receiver);
+        block_on(sender.close_and_drain());
+    }
+
+    #[test]
+    fn closed_future() {
+        let (sender, receiver) = mpsc::<u32>();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut closed = sender.closed();
+        assert!(Pin::new(&mut closed).poll(&mut cx).is_pending());
+        drop(receiver);
+        assert!(Pin::new(&mut closed).poll(&mut cx).is_ready());
+        block_on(sender.closed());
+    }
+
+    #[test]
+    fn receiver_close() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        sender.send(1).unwrap();
+        receiver.close();
+        assert_eq!(sender.send(2), Err(SendError::Closed(2)));
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), vec![1]);
+    }
+
+    #[test]
+    fn receiver_drop_drops_values() {
+        let (sender, receiver) = mpsc::<Arc<()>>();
+        let value = Arc::new(());
+        sender.send(value.clone()).unwrap();
+        assert_eq!(Arc::strong_count(&value), 2);
+        drop(receiver);
+        assert_eq!(Arc::strong_count(&value), 1);
+        // a value that holds a sender must not deadlock when dropped
+        struct Holder(#[allow(dead_code)] Sender<Holder>);
+        let (sender, receiver) = mpsc::<Holder>();
+        sender.send(Holder(sender.clone())).unwrap();
+        drop(receiver);
+    }
+
+    #[test]
+    fn extend() {
+        let (mut sender, mut receiver) = mpsc::<u32>();
+        sender.extend(0..3);
+        drop(sender);
+        assert_eq!(
+            block_on(receiver.by_ref().collect::<Vec<_>>()),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn blocking_recv() {
+        let (sender, mut receiver) = bounded::<u32>(0);
+        let producer = std::thread::spawn(move || {
+            for i in 0..10 {
+                block_on(sender.send_async(i)).unwrap();
+            }
+        });
+        for i in 0..10 {
+            assert_eq!(receiver.recv(), Ok(i));
+        }
+        assert_eq!(receiver.recv(), Err(RecvError::Disconnected));
+        producer.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn recv_timeout() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let timeout = Duration::from_millis(10);
+        assert_eq!(
+            receiver.recv_timeout(timeout),
+            Err(RecvTimeoutError::Timeout)
+        );
+        sender.send(1).unwrap();
+        assert_eq!(receiver.recv_timeout(timeout), Ok(1));
+        drop(sender);
+        assert_eq!(
+            receiver.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn blocking_iter() {
+        let (sender, receiver) = bounded::<u32>(1);
+        let producer = std::thread::spawn(move || {
+            for i in 0..10 {
+                block_on(sender.send_async(i)).unwrap();
+            }
+        });
+        let mut values = Vec::new();
+        for value in receiver {
+            values.push(value);
+        }
+        assert_eq!(values, (0..10).collect::<Vec<_>>());
+        producer.join().unwrap();
+    }
+
+    #[cfg(feature = "timer")]
+    #[test]
+    fn debounce() {
+        let (sender, receiver) = mpsc::<u32>();
+        let mut debounced = receiver.debounce(Duration::from_millis(50));
+        sender.send_all(0..3).unwrap();
+        assert_eq!(block_on(debounced.next()), Some(2));
+        sender.send(3).unwrap();
+        sender.send(4).unwrap();
+        drop(sender);
+        // no need to wait for the window when there won't be any more values
+        assert_eq!(block_on(debounced.collect::<Vec<_>>()), vec![4]);
+    }
+
+    #[cfg(feature = "timer")]
+    #[test]
+    fn idle_timeout() {
+        let (sender, receiver) = mpsc::<u32>();
+        let mut stream = receiver.with_idle_timeout(Duration::from_millis(20));
+        sender.send(0).unwrap();
+        assert_eq!(block_on(stream.next()), Some(Ok(0)));
+        // the stream keeps going after an idle gap
+        assert_eq!(block_on(stream.next()), Some(Err(Idle)));
+        assert_eq!(block_on(stream.next()), Some(Err(Idle)));
+        sender.send(1).unwrap();
+        drop(sender);
+        assert_eq!(block_on(stream.collect::<Vec<_>>()), vec![Ok(1)]);
+    }
+
+    #[test]
+    fn flush_waits_for_receiver() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let mut sink = sender.clone().sink();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        Pin::new(&mut sink).start_send(1).unwrap();
+        assert!(Pin::new(&mut sink).poll_flush(&mut cx).is_pending());
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert!(matches!(
+            Pin::new(&mut sink).poll_flush(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+        Pin::new(&mut sink).start_send(2).unwrap();
+        // closing flushes, so it waits as well
+        let flag = Arc::<Flag>::default();
+        assert!(flag.poll(&mut sink.close()).is_pending());
+        assert_eq!(receiver.try_recv(), Ok(2));
+        assert!(flag.woken());
+        assert!(matches!(flag.poll(&mut sink.close()), Poll::Ready(Ok(()))));
+        assert!(sink.is_closed());
+        // a flush fails once the receiver is gone
+        let mut sink = sender.sink();
+        Pin::new(&mut sink).start_send(3).unwrap();
+        drop(receiver);
+        assert!(matches!(
+            Pin::new(&mut sink).poll_flush(&mut cx),
+            Poll::Ready(Err(SinkError::ReceiverDropped))
+        ));
+    }
+
+    #[test]
+    fn buffered_sink() {
+        let (sender, mut receiver) = bounded::<u32>(2);
+        let mut sink = sender.sink().buffered(2);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        for i in 0..2 {
+            assert!(matches!(
+                Pin::new(&mut sink).poll_ready(&mut cx),
+                Poll::Ready(Ok(()))
+            ));
+            Pin::new(&mut sink).start_send(i).unwrap();
+        }
+        // nothing is sent until the buffer is full
+        assert!(receiver.is_empty());
+        assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_ready());
+        assert_eq!(receiver.len(), 2);
+        Pin::new(&mut sink).start_send(2).unwrap();
+        assert!(Pin::new(&mut sink).poll_close(&mut cx).is_pending());
+        assert_eq!(receiver.try_recv(), Ok(0));
+        // everything is in the queue now, but closing also waits until it is received
+        assert!(Pin::new(&mut sink).poll_close(&mut cx).is_pending());
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+        assert!(Pin::new(&mut sink).poll_close(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn buffered_sink_close() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        let mut sink = sender.sink().buffered(4);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        for i in 0..3 {
+            Pin::new(&mut sink).start_send(i).unwrap();
+        }
+        // closing waits until everything is in the queue
+        assert!(Pin::new(&mut sink).poll_close(&mut cx).is_pending());
+        assert_eq!(receiver.try_recv(), Ok(0));
+        assert!(Pin::new(&mut sink).poll_close(&mut cx).is_pending());
+        assert_eq!(sink.buffered_len(), 1);
+        drop(receiver);
+        assert!(matches!(
+            Pin::new(&mut sink).poll_close(&mut cx),
+            Poll::Ready(Err(SinkError::ReceiverDropped))
+        ));
+    }
+
+    #[test]
+    fn buffered_sink_feed_and_send() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let mut sink = sender.sink().buffered(4);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        // feeding only buffers the values
+        for i in 0..2 {
+            assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_ready());
+            Pin::new(&mut sink).start_send(i).unwrap();
+        }
+        assert_eq!(sink.buffered_len(), 2);
+        assert!(receiver.is_empty());
+        // flushing moves them to the queue, and waits for the receiver to take them
+        assert!(Pin::new(&mut sink).poll_flush(&mut cx).is_pending());
+        assert_eq!(sink.buffered_len(), 0);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![0, 1]);
+        assert!(matches!(
+            Pin::new(&mut sink).poll_flush(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+        // sending is feeding plus flushing
+        let (sent, received) = block_on(future::join(sink.send(2), receiver.next()));
+        assert!(sent.is_ok());
+        assert_eq!(received, Some(2));
+        assert_eq!(sink.buffered_len(), 0);
+    }
+
+    #[test]
+    fn combined_channel() {
+        let mut channel = Channel::<u32>::new();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        Pin::new(&mut channel).start_send(1).unwrap();
+        assert_eq!(block_on(channel.next()), Some(1));
+        Pin::new(&mut channel).start_send(2).unwrap();
+        let (sink, receiver) = channel.split();
+        // the receiver drains the queue after the sink is gone
+        drop(sink);
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), vec![2]);
+        let mut channel = Channel::from(bounded::<u32>(1));
+        Pin::new(&mut channel).start_send(1).unwrap();
+        assert!(Pin::new(&mut channel).poll_ready(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn poll_ready_n() {
+        let (sender, mut receiver) = bounded::<u32>(3);
+        sender.send_all(0..2).unwrap();
+        let mut sink = sender.sink();
+        let flag = Arc::<Flag>::default();
+        let waker = task::waker(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        assert!(sink.poll_ready_n(&mut cx, 1).is_ready());
+        assert!(sink.poll_ready_n(&mut cx, 2).is_pending());
+        assert_eq!(receiver.try_recv(), Ok(0));
+        assert!(flag.woken());
+        assert!(matches!(sink.poll_ready_n(&mut cx, 2), Poll::Ready(Ok(()))));
+        // more than the capacity would never be ready
+        assert!(matches!(
+            sink.poll_ready_n(&mut cx, 4),
+            Poll::Ready(Err(SinkError::Full))
+        ));
+    }
+
+    #[test]
+    fn sink_respects_capacity() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut a = sender.clone().sink();
+        let mut b = a.clone();
+        assert!(Pin::new(&mut a).poll_ready(&mut cx).is_ready());
+        // the room is claimed by a
+        assert!(Pin::new(&mut b).poll_ready(&mut cx).is_pending());
+        assert!(matches!(
+            Pin::new(&mut b).start_send(1),
+            Err(SinkError::Full)
+        ));
+        Pin::new(&mut a).start_send(2).unwrap();
+        assert!(matches!(
+            Pin::new(&mut a).start_send(3),
+            Err(SinkError::Full)
+        ));
+        assert_eq!(sender.queue_len(), 1);
+        assert_eq!(receiver.try_recv(), Ok(2));
+        // dropping a sink gives back the room it claimed
+        assert!(Pin::new(&mut a).poll_ready(&mut cx).is_ready());
+        drop(a);
+        assert!(Pin::new(&mut b).poll_ready(&mut cx).is_ready());
+        // a rendezvous channel takes one value for a waiting receiver
+        let (sender, mut receiver) = bounded::<u32>(0);
+        let mut a = sender.sink();
+        let mut b = a.clone();
+        assert!(Pin::new(&mut receiver).poll_next(&mut cx).is_pending());
+        assert!(Pin::new(&mut a).poll_ready(&mut cx).is_ready());
+        assert!(Pin::new(&mut b).poll_ready(&mut cx).is_pending());
+        Pin::new(&mut a).start_send(1).unwrap();
+        assert!(matches!(
+            Pin::new(&mut b).start_send(2),
+            Err(SinkError::Full)
+        ));
+        assert_eq!(receiver.try_recv(), Ok(1));
+    }
+
+    #[test]
+    fn clone_sink() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let mut sink1 = sender.sink();
+        let mut sink2 = sink1.clone();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut sink1).poll_close(&mut cx).is_ready());
+        assert!(sink1.clone().is_closed());
+        Pin::new(&mut sink2).start_send(1).unwrap();
+        assert_eq!(receiver.try_recv(), Ok(1));
+        drop(sink2);
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn sink_into_sender() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let mut sink = sender.sink();
+        Pin::new(&mut sink).start_send(1).unwrap();
+        let sender = sink.into_sender().unwrap();
+        sender.send(2).unwrap();
+        let mut sink = sender.sink();
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+        block_on(sink.close()).unwrap();
+        assert!(sink.is_closed());
+        assert!(sink.into_sender().is_none());
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn parked_senders() {
+        let (sender, receiver) = bounded::<u32>(0);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut sink1 = sender.clone().sink();
+        let mut sink2 = sender.clone().sink();
+        for _ in 0..3 {
+            assert!(Pin::new(&mut sink1).poll_ready(&mut cx).is_pending());
+            assert!(Pin::new(&mut sink2).poll_ready(&mut cx).is_pending());
+        }
+        // repeated polls don't register the same sender twice
+        assert_eq!(sender.0.lock().sender_wakers.waiters.len(), 2);
+        drop(sink1);
+        assert_eq!(sender.0.lock().sender_wakers.waiters.len(), 1);
+        drop(receiver);
+        assert!(matches!(
+            Pin::new(&mut sink2).poll_ready(&mut cx),
+            Poll::Ready(Err(SinkError::ReceiverDropped))
+        ));
+    }
+
+    #[test]
+    fn recv_async() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut recv = receiver.recv_async();
+        assert!(Pin::new(&mut recv).poll(&mut cx).is_pending());
+        drop(recv);
+        assert!(!receiver.has_waker());
+        sender.send(1).unwrap();
+        assert_eq!(block_on(receiver.recv_async()), Some(1));
+        drop(sender);
+        assert_eq!(block_on(receiver.recv_async()), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn timed() {
+        let (sender, mut receiver) = mpsc_timed::<u32>();
+        let before = Instant::now();
+        sender.send(0).unwrap();
+        sender.send(1).unwrap();
+        let (t0, v0) = receiver.try_recv().unwrap();
+        let (t1, v1) = receiver.try_recv().unwrap();
+        assert_eq!((v0, v1), (0, 1));
+        assert!(before <= t0 && t0 <= t1 && t1 <= Instant::now());
+        // with many senders the stamps are still in queue order
+        let threads = (0..4)
+            .map(|_| {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        sender.send(i).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(sender);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let stamps = block_on(receiver.map(|(t, _)| t).collect::<Vec<_>>());
+        assert_eq!(stamps.len(), 4000);
+        assert!(stamps.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn sender_is_empty() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        assert!(sender.is_empty());
+        sender.send_all(0..3).unwrap();
+        assert!(!sender.is_empty());
+        assert_eq!(receiver.drain_to_vec(), vec![0, 1, 2]);
+        assert!(sender.is_empty());
+        sender.send(3).unwrap();
+        assert_eq!(sender.high_water_mark(), 3);
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn byte_chunks() {
+        use bytes::Bytes;
+        let (sender, mut receiver) = mpsc::<Bytes>();
+        assert_eq!(receiver.recv_concat(), None);
+        sender.send_slice(b"ab").unwrap();
+        assert_eq!(receiver.recv_concat(), Some(Bytes::from_static(b"ab")));
+        // a single chunk is handed out as it is
+        let chunk = Bytes::from(vec![1, 2]);
+        let ptr = chunk.as_ptr();
+        sender.send(chunk).unwrap();
+        assert_eq!(receiver.recv_concat().unwrap().as_ptr(), ptr);
+        sender.send_slice(b"cd").unwrap();
+        sender.send_slice(b"e").unwrap();
+        assert_eq!(receiver.recv_concat(), Some(Bytes::from_static(b"cde")));
+    }
+
+    #[test]
+    fn recv_or_closed() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        sender.send(1).unwrap();
+        sender.close();
+        assert_eq!(block_on(receiver.recv_or_closed()), RecvResult::Value(1));
+        assert_eq!(block_on(receiver.recv_or_closed()), RecvResult::Closed);
+    }
+
+    #[test]
+    fn into_chunks() {
+        let (sender, receiver) = mpsc::<u32>();
+        let mut chunks = receiver.into_chunks(2);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        sender.send(0).unwrap();
+        assert!(Pin::new(&mut chunks).poll_next(&mut cx).is_pending());
+        sender.send_all(1..5).unwrap();
+        drop(sender);
+        assert_eq!(
+            block_on(chunks.collect::<Vec<_>>()),
+            vec![vec![0, 1], vec![2, 3], vec![4]]
+        );
+    }
+
+    /// a waker that remembers if it was woken
+    #[derive(Default)]
+    struct Flag(AtomicBool);
+
+    impl Flag {
+        fn woken(&self) -> bool {
+            self.0.load(Ordering::SeqCst)
+        }
+
+        /// poll `future` once with this as the waker, and reset the flag
+        fn poll<F: Future + Unpin>(self: &Arc<Self>, future: &mut F) -> Poll<F::Output> {
+            self.0.store(false, Ordering::SeqCst);
+            let waker = task::waker(self.clone());
+            Pin::new(future).poll(&mut Context::from_waker(&waker))
+        }
+    }
+
+    impl ArcWake for Flag {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// a waker that counts how often it was woken
+    #[derive(Default)]
+    struct WakeCount(AtomicUsize);
+
+    impl ArcWake for WakeCount {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn wakeups_are_coalesced() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let count = Arc::<WakeCount>::default();
+        let waker = task::waker(count.clone());
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut receiver).poll_next(&mut cx).is_pending());
+        sender.send_all(0..100).unwrap();
+        assert_eq!(count.0.load(Ordering::SeqCst), 1);
+        // the receiver only parks again once it has taken everything
+        for i in 0..100 {
+            assert_eq!(
+                Pin::new(&mut receiver).poll_next(&mut cx),
+                Poll::Ready(Some(i))
+            );
+        }
+        assert!(Pin::new(&mut receiver).poll_next(&mut cx).is_pending());
+        for i in 0..100 {
+            sender.send(i).unwrap();
+        }
+        assert_eq!(count.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn all_senders_dropped() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let other = sender.clone();
+        sender.send(0).unwrap();
+        let flag = Arc::<Flag>::default();
+        let mut dropped = receiver.all_senders_dropped();
+        assert!(flag.poll(&mut dropped).is_pending());
+        drop(sender);
+        assert!(!flag.woken());
+        drop(other);
+        assert!(flag.woken());
+        assert!(flag.poll(&mut dropped).is_ready());
+        drop(dropped);
+        // still resolves when the queue is not empty, and right away after the fact
+        assert_eq!(receiver.len(), 1);
+        block_on(receiver.all_senders_dropped());
+    }
+
+    #[test]
+    fn close_reason() {
+        let (sender, receiver) = mpsc::<u32>();
+        assert_eq!(receiver.close_reason(), None);
+        drop(sender);
+        assert_eq!(
+            receiver.close_reason(),
+            Some(CloseReason::AllSendersDropped)
+        );
+        let (sender, mut receiver) = mpsc::<u32>();
+        sender.send(0).unwrap();
+        sender.abort();
+        // the first reason sticks
+        sender.close();
+        drop(sender);
+        assert_eq!(block_on(receiver.next()), Some(0));
+        assert_eq!(block_on(receiver.next()), None);
+        assert_eq!(receiver.close_reason(), Some(CloseReason::Aborted));
+    }
+
+    #[cfg(feature = "smallvec")]
+    #[test]
+    fn recv_many_smallvec() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        sender.send_all(0..5).unwrap();
+        let batch = receiver.recv_many_smallvec::<4>();
+        assert!(!batch.spilled());
+        assert_eq!(batch.as_slice(), &[0, 1, 2, 3]);
+        assert_eq!(receiver.recv_many_smallvec::<4>().as_slice(), &[4]);
+        assert!(receiver.recv_many_smallvec::<4>().is_empty());
+    }
+
+    #[test]
+    fn drain_filter() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        sender.send_priority(1, true).unwrap();
+        sender.send_priority(2, true).unwrap();
+        sender.send_all(3..7).unwrap();
+        assert_eq!(receiver.drain_filter(|x| x % 2 == 0), vec![2, 4, 6]);
+        assert_eq!(receiver.drain_filter(|x| *x > 10), Vec::<u32>::new());
+        // the remaining high priority value is still in front of new ones
+        sender.send_priority(7, true).unwrap();
+        drop(sender);
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), vec![1, 7, 3, 5]);
+        // removing values is not receiving them
+        let dequeued = Arc::new(AtomicUsize::new(0));
+        let (sender, mut receiver) = {
+            let dequeued = dequeued.clone();
+            mpsc_observed::<u32>(move |event| {
+                if let ChannelEvent::Dequeued { .. } = event {
+                    dequeued.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        };
+        sender.send_all(0..4).unwrap();
+        assert_eq!(receiver.drain_filter(|x| x % 2 == 0), vec![0, 2]);
+        assert_eq!(dequeued.load(Ordering::SeqCst), 0);
+        #[cfg(feature = "metrics")]
+        assert_eq!(receiver.metrics().total_received, 0);
+    }
+
+    #[test]
+    fn split_off() {
+        let (sender, mut receiver) = bounded::<u32>(4);
+        sender.send_all(0..4).unwrap();
+        let other = receiver.split_off(1);
+        // the moved values make room in the original queue
+        assert_eq!(sender.try_send(4), Ok(2));
+        assert_eq!(block_on(other.collect::<Vec<_>>()), vec![1, 2, 3]);
+        assert!(receiver.split_off(10).is_empty());
+        drop(sender);
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), vec![0, 4]);
+        // moving values is not receiving them
+        let dequeued = Arc::new(AtomicUsize::new(0));
+        let (sender, mut receiver) = {
+            let dequeued = dequeued.clone();
+            mpsc_observed::<u32>(move |event| {
+                if let ChannelEvent::Dequeued { .. } = event {
+                    dequeued.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        };
+        sender.send_all(0..4).unwrap();
+        let other = receiver.split_off(2);
+        assert_eq!(dequeued.load(Ordering::SeqCst), 0);
+        #[cfg(feature = "metrics")]
+        {
+            assert_eq!(receiver.metrics().total_received, 0);
+            assert_eq!(other.metrics().total_sent, 2);
+        }
+        assert_eq!(other.len(), 2);
+        assert_eq!(receiver.len(), 2);
+    }
+
+    #[test]
+    fn observed() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (sender, mut receiver) = {
+            let events = events.clone();
+            mpsc_observed::<u32>(move |event| events.lock().unwrap().push(event))
+        };
+        let other = sender.clone();
+        sender.send_all(0..2).unwrap();
+        assert_eq!(receiver.try_recv(), Ok(0));
+        drop(other);
+        drop(receiver);
+        use ChannelEvent::*;
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                Enqueued { len: 1 },
+                Enqueued { len: 2 },
+                Dequeued { len: 1 },
+                SenderDropped { remaining: 1 },
+                ReceiverDropped,
+            ]
+        );
+    }
+
+    #[test]
+    fn notify() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        sender.notify();
+        let flag = Arc::<Flag>::default();
+        assert!(flag.poll(&mut receiver.next()).is_pending());
+        sender.notify();
+        assert!(flag.woken());
+        // nothing was sent, so the receiver just parks again
+        assert!(flag.poll(&mut receiver.next()).is_pending());
+    }
+
+    #[test]
+    fn pause_resume() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        sender.send(0).unwrap();
+        receiver.pause();
+        let flag = Arc::<Flag>::default();
+        assert!(flag.poll(&mut receiver.next()).is_pending());
+        // senders still see a full queue
+        assert_eq!(sender.try_send(1), Err(TrySendError::Full(1)));
+        drop(sender);
+        assert!(!flag.woken());
+        receiver.resume();
+        assert!(flag.woken());
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), vec![0]);
+    }
+
+    #[test]
+    fn no_lost_wakeups() {
+        // every interleaving of two senders that each send twice and then drop
+        for order in (0u32..64).filter(|order| order.count_ones() == 3) {
+            let (sender, mut receiver) = mpsc::<u32>();
+            let mut senders = [Some(sender.clone()), Some(sender)];
+            let mut sent = [0; 2];
+            let mut received = Vec::new();
+            let flag = Arc::<Flag>::default();
+            assert!(flag.poll(&mut receiver.next()).is_pending());
+            for step in 0..6 {
+                let i = ((order >> step) & 1) as usize;
+                if sent[i] < 2 {
+                    senders[i].as_ref().unwrap().send(i as u32).unwrap();
+                    sent[i] += 1;
+                } else {
+                    senders[i] = None;
+                }
+                // unless the receiver was woken, it must not have anything to do
+                let woken = flag.woken();
+                loop {
+                    match flag.poll(&mut receiver.next()) {
+                        Poll::Ready(Some(value)) => {
+                            assert!(woken, "lost wakeup for order {:06b}", order);
+                            received.push(value);
+                        }
+                        Poll::Ready(None) => {
+                            assert!(woken, "lost wakeup for order {:06b}", order);
+                            break;
+                        }
+                        Poll::Pending => break,
+                    }
+                }
+            }
+            assert_eq!(received.len(), 4);
+            assert!(receiver.is_terminated());
+        }
+    }
+
+    #[test]
+    fn receiving_wakes_senders() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        sender.send(0).unwrap();
+        let flag = Arc::<Flag>::default();
+        let mut send = sender.send_async(1);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = Vec::new();
+        // every way of taking a value out of the queue has to wake a parked sender
+        for i in 0..4 {
+            assert!(flag.poll(&mut send).is_pending());
+            match i {
+                0 => assert!(Pin::new(&mut receiver).poll_next(&mut cx).is_ready()),
+                1 => assert!(receiver.try_recv().is_ok()),
+                2 => assert_eq!(receiver.recv_many(&mut buf, 1), 1),
+                _ => assert!(receiver.poll_recv_many(&mut cx, &mut buf, 1).is_ready()),
+            }
+            assert!(flag.woken());
+            assert!(flag.poll(&mut send).is_ready());
+            send = sender.send_async(1);
+        }
+    }
+
+    #[test]
+    fn fair_sender_wakeups() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        sender.send(0).unwrap();
+        let flags = (0..3).map(|_| Arc::<Flag>::default()).collect::<Vec<_>>();
+        let mut sends = (1..4).map(|i| sender.send_async(i)).collect::<Vec<_>>();
+        for (send, flag) in sends.iter_mut().zip(&flags) {
+            assert!(flag.poll(send).is_pending());
+        }
+        let woken = || flags.iter().map(|flag| flag.woken()).collect::<Vec<_>>();
+        assert_eq!(receiver.try_recv(), Ok(0));
+        assert_eq!(woken(), vec![true, false, false]);
+        // the first sender gives up, so its turn goes to the next one
+        sends.remove(0);
+        assert_eq!(woken(), vec![true, true, false]);
+    }
+
+    #[test]
+    fn reserve() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        let permit = block_on(sender.reserve()).unwrap();
+        assert!(matches!(sender.try_send(1), Err(TrySendError::Full(1))));
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut reserve = sender.reserve();
+        assert!(Pin::new(&mut reserve).poll(&mut cx).is_pending());
+        // an unused permit gives the slot back
+        drop(permit);
+        let permit = match Pin::new(&mut reserve).poll(&mut cx) {
+            Poll::Ready(Ok(permit)) => permit,
+            _ => panic!("reserve should have completed"),
+        };
+        permit.send(2);
+        assert_eq!(receiver.try_recv(), Ok(2));
+    }
+
+    #[test]
+    fn permit_after_close() {
+        let (sender, mut receiver) = bounded::<u32>(2);
+        let flag = Arc::<Flag>::default();
+        let permit = block_on(sender.reserve()).unwrap();
+        let unused = block_on(sender.reserve()).unwrap();
+        sender.close();
+        // the stream can't end while a permit can still send
+        assert!(flag.poll(&mut receiver.next()).is_pending());
+        assert!(!receiver.is_terminated());
+        permit.send(1);
+        assert!(flag.woken());
+        assert_eq!(flag.poll(&mut receiver.next()), Poll::Ready(Some(1)));
+        assert!(flag.poll(&mut receiver.next()).is_pending());
+        drop(unused);
+        assert!(flag.woken());
+        assert!(receiver.is_terminated());
+        assert_eq!(flag.poll(&mut receiver.next()), Poll::Ready(None));
+    }
+
+    #[test]
+    fn try_reserve_owned() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        let permit = sender.try_reserve_owned().map_err(|(_, e)| e).unwrap();
+        // the permit can be moved into a task that needs a 'static value
+        let sender = std::thread::spawn(move || permit.send(1)).join().unwrap();
+        let permit = sender.try_reserve_owned();
+        let sender = match permit {
+            Err((sender, TrySendError::Full(()))) => sender,
+            _ => panic!("queue should be full"),
+        };
+        assert_eq!(receiver.try_recv(), Ok(1));
+        let permit = sender.try_reserve_owned().map_err(|(_, e)| e).unwrap();
+        assert!(matches!(
+            permit.sender.as_ref().unwrap().try_send(2),
+            Err(TrySendError::Full(2))
+        ));
+        // an unused permit gives the slot back, and the sender with it
+        drop(permit);
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn map_filter() {
+        let (sender, receiver) = mpsc::<u32>();
+        sender.send_all(0..6).unwrap();
+        drop(sender);
+        let stream = receiver.filter(|x| x % 2 == 0);
+        assert_eq!(block_on(stream.collect::<Vec<_>>()), vec![0, 2, 4]);
+        let (sender, receiver) = mpsc::<u32>();
+        sender.send_all(0..2).unwrap();
+        drop(sender);
+        let mut stream = receiver.map(|x| x * 10);
+        assert_eq!(block_on((&mut stream).collect::<Vec<_>>()), vec![0, 10]);
+        assert!(stream.is_terminated());
+    }
+
+    #[test]
+    fn with_depth() {
+        let (sender, receiver) = mpsc::<u32>();
+        sender.send_all(0..3).unwrap();
+        drop(sender);
+        let mut stream = receiver.with_depth();
+        assert!(!stream.is_terminated());
+        assert_eq!(
+            block_on((&mut stream).collect::<Vec<_>>()),
+            vec![(0, 2), (1, 1), (2, 0)]
+        );
+        assert!(stream.is_terminated());
+    }
+
+    #[test]
+    fn send_async_waits_for_room() {
+        let (sender, mut receiver) = bounded::<u32>(1);
+        block_on(sender.send_async(1)).unwrap();
+        let mut send = sender.send_async(2);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut send).poll(&mut cx).is_pending());
+        assert_eq!(block_on(receiver.next()), Some(1));
+        assert!(matches!(
+            Pin::new(&mut send).poll(&mut cx),
+            Poll::Ready(Ok(1))
+        ));
+        // a dropped future neither sends its value nor leaves a waker behind
+        let mut send = sender.send_async(3);
+        assert!(Pin::new(&mut send).poll(&mut cx).is_pending());
+        drop(send);
+        assert!(sender.0.lock().sender_wakers.waiters.is_empty());
+        assert_eq!(sender.queue_len(), 1);
+        // dropping the receiver resolves a parked send
+        let mut send = sender.send_async(4);
+        assert!(Pin::new(&mut send).poll(&mut cx).is_pending());
+        drop(receiver);
+        assert!(matches!(
+            Pin::new(&mut send).poll(&mut cx),
+            Poll::Ready(Err(SendError::ReceiverDropped(4)))
+        ));
+    }
+
+    #[cfg(feature = "crossbeam")]
+    #[test]
+    fn lock_free_sends() {
+        let (sender, receiver) = mpsc::<(u32, u32)>();
+        let threads = (0..16)
+            .map(|i| {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    for j in 0..1000 {
+                        sender.send((i, j)).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(sender);
+        let values = block_on(receiver.collect::<Vec<_>>());
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(values.len(), 16000);
+        // the values of every sender arrive in the order they were sent
+        let mut next = [0; 16];
+        for (i, j) in values {
+            assert_eq!(next[i as usize], j);
+            next[i as usize] += 1;
+        }
+    }
+
+    #[cfg(feature = "crossbeam")]
+    #[test]
+    fn lock_free_sends_before_close() {
+        let (sender, mut receiver) = mpsc::<u32>();
+        let threads = (0..4)
+            .map(|_| {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    let mut sent = 0;
+                    while sent < 100_000 && sender.send(sent).is_ok() {
+                        sent += 1;
+                    }
+                    sent
+                })
+            })
+            .collect::<Vec<_>>();
+        while receiver.len() < 1000 {
+            std::thread::yield_now();
+        }
+        sender.close();
+        // every value that was sent successfully is there before the stream ends
+        let received = block_on((&mut receiver).fold(0, |n, _| async move { n + 1 }));
+        let sent: u32 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(received, sent as usize);
+        assert_eq!(sender.send(0), Err(SendError::Closed(0)));
+    }
 }
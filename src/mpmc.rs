@@ -0,0 +1,190 @@
+//! a queue with multiple consumers, where each value is delivered to exactly one of them
+use crate::{
+    sync::{lock, Mutex},
+    Consumer, Producer, SendError, TryRecvError, WaitQueue,
+};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{pin::Pin, task::Context};
+use futures::{
+    stream::{FusedStream, Stream},
+    task::Poll,
+};
+
+struct MpmcInner<T> {
+    queue: VecDeque<T>,
+    receiver_wakers: WaitQueue,
+    /// explicit counts, since with multiple receivers the strong count of the arc is
+    /// no longer enough to tell if all senders are gone
+    sender_count: usize,
+    receiver_count: usize,
+}
+
+pub struct Sender<T>(Arc<Mutex<MpmcInner<T>>>);
+
+impl<T> Sender<T> {
+    /// current queue len. This can be used to detect when the receivers are lagging
+    pub fn queue_len(&self) -> usize {
+        lock(&self.0).queue.len()
+    }
+
+    // true if all receivers are dropped, and therefore there is no point in sending anymore
+    pub fn is_cancelled(&self) -> bool {
+        lock(&self.0).receiver_count == 0
+    }
+
+    /// send a value, returning the queue len after the send
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut inner = lock(&self.0);
+        if inner.receiver_count == 0 {
+            return Err(SendError::ReceiverDropped(value));
+        }
+        inner.queue.push_back(value);
+        // we don't know which receiver is going to be polled next, so wake them all.
+        // the ones that don't get the value will just park again.
+        inner.receiver_wakers.wake_all();
+        Ok(inner.queue.len())
+    }
+}
+
+impl<T> Producer<T> for Sender<T> {
+    fn try_send(&self, value: T) -> Result<(), SendError<T>> {
+        self.send(value).map(|_| ())
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    fn is_cancelled(&self) -> bool {
+        Sender::is_cancelled(self)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        lock(&self.0).sender_count += 1;
+        Sender(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.0);
+        inner.sender_count -= 1;
+        if inner.sender_count == 0 {
+            inner.receiver_wakers.wake_all();
+        }
+    }
+}
+
+/// a receiver that can be cloned to share the work with other receivers
+pub struct Receiver<T> {
+    inner: Arc<Mutex<MpmcInner<T>>>,
+    key: Option<usize>,
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        lock(&self.inner).receiver_count += 1;
+        Receiver {
+            inner: self.inner.clone(),
+            key: None,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.inner);
+        inner.receiver_count -= 1;
+        inner.receiver_wakers.remove(self.key.take());
+    }
+}
+
+impl<T> Consumer<T> for Receiver<T> {
+    fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut inner = lock(&self.inner);
+        if let Some(value) = inner.queue.pop_front() {
+            Ok(value)
+        } else if inner.sender_count == 0 {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let mut inner = lock(&this.inner);
+        if let Some(value) = inner.queue.pop_front() {
+            Poll::Ready(Some(value))
+        } else if inner.sender_count == 0 {
+            Poll::Ready(None)
+        } else {
+            inner.receiver_wakers.register(&mut this.key, ctx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> FusedStream for Receiver<T> {
+    fn is_terminated(&self) -> bool {
+        let inner = lock(&self.inner);
+        inner.queue.is_empty() && inner.sender_count == 0
+    }
+}
+
+/// an unbounded queue with cloneable receivers, each value goes to exactly one of them
+pub fn mpmc<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Mutex::new(MpmcInner {
+        queue: VecDeque::new(),
+        receiver_wakers: WaitQueue::default(),
+        sender_count: 1,
+        receiver_count: 1,
+    }));
+    let receiver = Receiver {
+        inner: inner.clone(),
+        key: None,
+    };
+    (Sender(inner), receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use futures::{executor::block_on, future, stream::StreamExt};
+
+    #[test]
+    fn each_value_once() {
+        let (sender, receiver) = mpmc::<u32>();
+        let workers = (0..4)
+            .map(|_| receiver.clone().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        drop(receiver);
+        let producer = async move {
+            for i in 0..100 {
+                sender.send(i).unwrap();
+                // give the workers a chance to run
+                let mut yielded = false;
+                future::poll_fn(|cx| {
+                    if yielded {
+                        Poll::Ready(())
+                    } else {
+                        yielded = true;
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                })
+                .await;
+            }
+        };
+        let ((), results) = block_on(future::join(producer, future::join_all(workers)));
+        let mut all = results.into_iter().flatten().collect::<Vec<_>>();
+        all.sort();
+        assert_eq!(all, (0..100).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,129 @@
+//! a channel for a single value, e.g. for the response to a request
+use crate::{
+    sync::{lock, Mutex},
+    SendError,
+};
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Waker},
+};
+use futures::task::Poll;
+#[cfg(feature = "std")]
+use std::error;
+
+/// the sender was dropped without sending a value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Canceled;
+
+#[cfg(feature = "std")]
+impl error::Error for Canceled {}
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Canceled")
+    }
+}
+
+struct OneshotInner<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+    /// set when the sender is gone, either because it sent a value or got dropped
+    sender_done: bool,
+    receiver_dropped: bool,
+}
+
+pub struct Sender<T>(Arc<Mutex<OneshotInner<T>>>);
+
+impl<T> Sender<T> {
+    /// send the value, waking the receiver
+    pub fn send(self, value: T) -> Result<(), SendError<T>> {
+        let mut inner = lock(&self.0);
+        if inner.receiver_dropped {
+            return Err(SendError::ReceiverDropped(value));
+        }
+        inner.value = Some(value);
+        // drop will wake the receiver
+        Ok(())
+    }
+
+    // true if the receiver is dropped, and therefore there is no point in sending anymore
+    pub fn is_cancelled(&self) -> bool {
+        lock(&self.0).receiver_dropped
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.0);
+        inner.sender_done = true;
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// a future that resolves to the value, or to `Canceled` if the sender is dropped
+/// without sending
+pub struct Receiver<T>(Arc<Mutex<OneshotInner<T>>>);
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = lock(&self.0);
+        if let Some(value) = inner.value.take() {
+            Poll::Ready(Ok(value))
+        } else if inner.sender_done {
+            Poll::Ready(Err(Canceled))
+        } else {
+            inner.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.0);
+        inner.receiver_dropped = true;
+        inner.waker = None;
+        let value = inner.value.take();
+        // the value might hold a sender, so don't drop it under the lock
+        drop(inner);
+        drop(value);
+    }
+}
+
+/// a channel for exactly one value
+pub fn oneshot<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Mutex::new(OneshotInner {
+        value: None,
+        waker: None,
+        sender_done: false,
+        receiver_dropped: false,
+    }));
+    (Sender(inner.clone()), Receiver(inner))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn send_or_cancel() {
+        let (sender, receiver) = oneshot::<u32>();
+        sender.send(1).unwrap();
+        assert_eq!(block_on(receiver), Ok(1));
+        let (sender, receiver) = oneshot::<u32>();
+        drop(sender);
+        assert_eq!(block_on(receiver), Err(Canceled));
+        let (sender, receiver) = oneshot::<u32>();
+        drop(receiver);
+        assert!(sender.is_cancelled());
+        assert_eq!(sender.send(1), Err(SendError::ReceiverDropped(1)));
+    }
+}
@@ -0,0 +1,164 @@
+//! a queue that always yields the largest value first
+use crate::{
+    sync::{lock, Mutex},
+    Consumer, Producer, SendError, TryRecvError,
+};
+use alloc::{collections::BinaryHeap, sync::Arc};
+use core::{
+    mem,
+    pin::Pin,
+    task::{Context, Waker},
+};
+use futures::{
+    stream::{FusedStream, Stream},
+    task::Poll,
+};
+
+struct HeapInner<T> {
+    heap: BinaryHeap<T>,
+    waker: Option<Waker>,
+    receiver_dropped: bool,
+    sender_count: usize,
+}
+
+pub struct Sender<T>(Arc<Mutex<HeapInner<T>>>);
+
+impl<T> Sender<T> {
+    /// current queue len. This can be used to detect when the receiver is lagging
+    pub fn queue_len(&self) -> usize {
+        lock(&self.0).heap.len()
+    }
+
+    // true if the receiver is dropped, and therefore there is no point in sending anymore
+    pub fn is_cancelled(&self) -> bool {
+        lock(&self.0).receiver_dropped
+    }
+}
+
+impl<T: Ord> Sender<T> {
+    /// send a value, returning the queue len after the send
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut inner = lock(&self.0);
+        if inner.receiver_dropped {
+            return Err(SendError::ReceiverDropped(value));
+        }
+        inner.heap.push(value);
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+        Ok(inner.heap.len())
+    }
+}
+
+impl<T: Ord> Producer<T> for Sender<T> {
+    fn try_send(&self, value: T) -> Result<(), SendError<T>> {
+        self.send(value).map(|_| ())
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    fn is_cancelled(&self) -> bool {
+        Sender::is_cancelled(self)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        lock(&self.0).sender_count += 1;
+        Sender(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.0);
+        inner.sender_count -= 1;
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// yields the largest queued value, no matter when it was sent
+pub struct Receiver<T>(Arc<Mutex<HeapInner<T>>>);
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.0);
+        inner.receiver_dropped = true;
+        inner.waker = None;
+        let heap = mem::take(&mut inner.heap);
+        // values might hold senders, so don't drop them under the lock
+        drop(inner);
+        drop(heap);
+    }
+}
+
+impl<T: Ord> Consumer<T> for Receiver<T> {
+    fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut inner = lock(&self.0);
+        if let Some(value) = inner.heap.pop() {
+            Ok(value)
+        } else if inner.sender_count == 0 {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+}
+
+impl<T: Ord> Stream for Receiver<T> {
+    type Item = T;
+    fn poll_next(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut inner = lock(&self.0);
+        if let Some(value) = inner.heap.pop() {
+            Poll::Ready(Some(value))
+        } else if inner.sender_count == 0 {
+            Poll::Ready(None)
+        } else {
+            inner.waker = Some(ctx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T: Ord> FusedStream for Receiver<T> {
+    fn is_terminated(&self) -> bool {
+        let inner = lock(&self.0);
+        inner.heap.is_empty() && inner.sender_count == 0
+    }
+}
+
+/// an unbounded queue where the receiver always gets the largest queued value.
+///
+/// Values that compare equal are not necessarily received in the order they were sent.
+pub fn priority_queue<T: Ord>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Mutex::new(HeapInner {
+        heap: BinaryHeap::new(),
+        waker: None,
+        receiver_dropped: false,
+        sender_count: 1,
+    }));
+    (Sender(inner.clone()), Receiver(inner))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use futures::{executor::block_on, stream::StreamExt};
+
+    #[test]
+    fn largest_first() {
+        let (sender, mut receiver) = priority_queue::<u32>();
+        sender.send(1).unwrap();
+        sender.send(3).unwrap();
+        sender.send(2).unwrap();
+        assert_eq!(block_on(receiver.next()), Some(3));
+        sender.send(4).unwrap();
+        drop(sender);
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), vec![4, 2, 1]);
+    }
+}
@@ -0,0 +1,24 @@
+//! the lock that protects the queue state. Without std this is a spin lock.
+#[cfg(feature = "std")]
+pub(crate) use std::sync::{Mutex, MutexGuard};
+
+#[cfg(all(not(feature = "std"), feature = "spin"))]
+pub(crate) use spin::{Mutex, MutexGuard};
+
+#[cfg(not(any(feature = "std", feature = "spin")))]
+compile_error!("kiss-queue needs either the std or the spin feature for locking");
+
+/// lock a mutex, ignoring poisoning.
+///
+/// The queue state stays consistent even if a thread panics while holding the lock, so
+/// there is no reason to make every other user of the queue panic as well.
+#[cfg(feature = "std")]
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// lock a mutex. A spin lock has no poisoning to deal with.
+#[cfg(all(not(feature = "std"), feature = "spin"))]
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock()
+}
@@ -0,0 +1,185 @@
+//! a channel that only keeps the latest value, for broadcasting state
+use crate::{
+    sync::{lock, Mutex, MutexGuard},
+    Producer, SendError,
+};
+use alloc::sync::Arc;
+use core::{
+    ops::Deref,
+    pin::Pin,
+    task::{Context, Waker},
+};
+use futures::{
+    stream::{FusedStream, Stream},
+    task::Poll,
+};
+
+struct WatchInner<T> {
+    value: T,
+    /// incremented on every send, so the receiver can tell if it has seen a value
+    version: u64,
+    waker: Option<Waker>,
+    receiver_dropped: bool,
+    sender_count: usize,
+}
+
+pub struct Sender<T>(Arc<Mutex<WatchInner<T>>>);
+
+impl<T> Sender<T> {
+    /// replace the current value, waking the receiver
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut inner = lock(&self.0);
+        if inner.receiver_dropped {
+            return Err(SendError::ReceiverDropped(value));
+        }
+        inner.value = value;
+        inner.version += 1;
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    // true if the receiver is dropped, and therefore there is no point in sending anymore
+    pub fn is_cancelled(&self) -> bool {
+        lock(&self.0).receiver_dropped
+    }
+}
+
+/// the queue holds a single value, which is replaced by every send
+impl<T> Producer<T> for Sender<T> {
+    fn try_send(&self, value: T) -> Result<(), SendError<T>> {
+        self.send(value)
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        Sender::is_cancelled(self)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        lock(&self.0).sender_count += 1;
+        Sender(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.0);
+        inner.sender_count -= 1;
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// yields the latest value whenever it has changed since the last one it yielded
+pub struct Receiver<T> {
+    inner: Arc<Mutex<WatchInner<T>>>,
+    /// the version of the last value we yielded
+    seen: u64,
+}
+
+impl<T> Receiver<T> {
+    /// the latest value, without marking it as seen.
+    ///
+    /// The stream still yields the value if it has not done so yet. This holds the lock
+    /// until the `Ref` is dropped, which blocks senders, so keep it short.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        Ref(lock(&self.inner))
+    }
+}
+
+/// the latest value of a watch channel, see `Receiver::borrow`
+pub struct Ref<'a, T>(MutexGuard<'a, WatchInner<T>>);
+
+impl<T> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0.value
+    }
+}
+
+impl<T: Clone> Stream for Receiver<T> {
+    type Item = T;
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let mut inner = lock(&this.inner);
+        if inner.version != this.seen {
+            this.seen = inner.version;
+            Poll::Ready(Some(inner.value.clone()))
+        } else if inner.sender_count == 0 {
+            Poll::Ready(None)
+        } else {
+            inner.waker = Some(ctx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T: Clone> FusedStream for Receiver<T> {
+    fn is_terminated(&self) -> bool {
+        let inner = lock(&self.inner);
+        inner.version == self.seen && inner.sender_count == 0
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.inner);
+        inner.receiver_dropped = true;
+        inner.waker = None;
+    }
+}
+
+/// a channel that stores only the latest value.
+///
+/// The receiver first yields `initial`, and then the latest value every time it changes.
+/// Values that are replaced before the receiver gets to them are skipped.
+pub fn watch<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Mutex::new(WatchInner {
+        value: initial,
+        version: 1,
+        waker: None,
+        receiver_dropped: false,
+        sender_count: 1,
+    }));
+    let receiver = Receiver {
+        inner: inner.clone(),
+        seen: 0,
+    };
+    (Sender(inner), receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, stream::StreamExt};
+
+    #[test]
+    fn latest_value() {
+        let (sender, mut receiver) = watch(0u32);
+        assert_eq!(block_on(receiver.next()), Some(0));
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        assert_eq!(block_on(receiver.next()), Some(2));
+        drop(sender);
+        assert_eq!(block_on(receiver.next()), None);
+    }
+
+    #[test]
+    fn borrow() {
+        let (sender, mut receiver) = watch(0u32);
+        assert_eq!(*receiver.borrow(), 0);
+        sender.send(1).unwrap();
+        assert_eq!(*receiver.borrow(), 1);
+        // borrowing does not count as seeing the value
+        assert_eq!(block_on(receiver.next()), Some(1));
+    }
+}
@@ -0,0 +1,46 @@
+//! counting waker clones needs a hand made `RawWaker`, which is unsafe code and can
+//! therefore not live in the crate itself
+use futures::{
+    stream::Stream,
+    task::{noop_waker, Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+use kiss_queue::mpsc;
+use std::{
+    pin::Pin,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+fn raw_waker() -> RawWaker {
+    RawWaker::new(ptr::null(), &VTABLE)
+}
+
+unsafe fn clone(_: *const ()) -> RawWaker {
+    CLONES.fetch_add(1, Ordering::SeqCst);
+    raw_waker()
+}
+
+unsafe fn noop(_: *const ()) {}
+
+#[test]
+fn repoll_does_not_clone_waker() {
+    let waker = unsafe { Waker::from_raw(raw_waker()) };
+    let (sender, mut receiver) = mpsc::<u32>();
+    let mut poll =
+        |waker: &Waker| Pin::new(&mut receiver).poll_next(&mut Context::from_waker(waker));
+    assert!(poll(&waker).is_pending());
+    assert_eq!(CLONES.load(Ordering::SeqCst), 1);
+    // the same task polls again
+    assert!(poll(&waker).is_pending());
+    assert_eq!(CLONES.load(Ordering::SeqCst), 1);
+    // another task replaces the waker, so we have to store ours again
+    assert!(poll(&noop_waker()).is_pending());
+    assert!(poll(&waker).is_pending());
+    assert_eq!(CLONES.load(Ordering::SeqCst), 2);
+    sender.send(1).unwrap();
+    assert_eq!(poll(&waker), Poll::Ready(Some(1)));
+}